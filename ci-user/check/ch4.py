@@ -7,6 +7,14 @@ EXPECTED += [
     "Test 04_4 test OK!",
     "Test 04_5 ummap OK!",
     "Test 04_6 ummap2 OK!",
+    "Test mmap address OK!",
+    "Test sleep blocking OK!",
+    "Test mmap lazy OK!",
+    "Test partial munmap and mprotect OK!",
+    "Test shared memory OK!",
+    "Test brk OK!",
+    "Test fork exec OK!",
+    "Test copy on write OK!",
 ]
 
 NOT_EXPECTED += [
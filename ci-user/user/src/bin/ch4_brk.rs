@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{brk, exit, fork, mmap, munmap, sbrk, waitpid};
+
+/*
+理想结果：输出 Test brk OK!
+*/
+
+const PAGE: usize = 4096;
+
+#[no_mangle]
+fn main() -> i32 {
+    let old = brk(0);
+    assert!(old > 0);
+    // 堆向上增长，新页可以读写
+    assert_eq!(old, sbrk((PAGE * 4) as isize));
+    assert_eq!(old + (PAGE * 4) as isize, brk(0));
+    for addr in (old as usize..old as usize + PAGE * 4).step_by(512) {
+        unsafe {
+            *(addr as *mut u8) = addr as u8;
+        }
+    }
+    for addr in (old as usize..old as usize + PAGE * 4).step_by(512) {
+        unsafe {
+            assert_eq!(*(addr as *const u8), addr as u8);
+        }
+    }
+    // 收缩后释放的页不能再访问
+    assert_eq!(old + (PAGE * 4) as isize, sbrk(-((PAGE * 2) as isize)));
+    assert_eq!(old + (PAGE * 2) as isize, brk(0));
+    let pid = fork();
+    if pid == 0 {
+        unsafe {
+            *((old as usize + PAGE * 3) as *mut u8) = 1;
+        }
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(pid, waitpid(pid as usize, &mut exit_code));
+    assert_eq!(-2, exit_code);
+    // 不能低于堆底，也不能长进已有的映射
+    assert_eq!(old + (PAGE * 2) as isize, brk(1));
+    let end = (old as usize + PAGE * 2 + PAGE - 1) & !(PAGE - 1);
+    assert_eq!((end + PAGE * 2) as isize, mmap(end + PAGE * 2, PAGE, 3));
+    assert_eq!(-1, sbrk((PAGE * 4) as isize));
+    assert_eq!(old + (PAGE * 2) as isize, brk(0));
+    assert_eq!(old, sbrk(-((PAGE * 2) as isize)));
+    assert_eq!(0, munmap(end + PAGE * 2, PAGE));
+    // 超过 16 KiB 的分配通过 brk 扩展用户堆
+    let mut v: Vec<u8> = Vec::new();
+    for i in 0..PAGE * 16 {
+        v.push(i as u8);
+    }
+    for (i, x) in v.iter().enumerate() {
+        assert_eq!(*x, i as u8);
+    }
+    assert!(brk(0) > old);
+    println!("Test brk OK!");
+    0
+}
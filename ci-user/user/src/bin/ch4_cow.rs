@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, mmap, sleep_blocking, waitpid};
+
+/*
+理想结果：输出 Test copy on write OK!
+*/
+
+const PAGE: usize = 4096;
+const PAGES: usize = 8;
+
+static mut GLOBAL: usize = 1;
+
+fn wait_child(pid: isize) -> i32 {
+    let mut exit_code: i32 = 0;
+    assert_eq!(pid, waitpid(pid as usize, &mut exit_code));
+    exit_code
+}
+
+fn page(start: usize, i: usize) -> *mut usize {
+    (start + PAGE * i) as *mut usize
+}
+
+#[no_mangle]
+fn main() -> i32 {
+    let start: usize = 0x10000000;
+    assert_eq!(start as isize, mmap(start, PAGE * PAGES, 3));
+    for i in 0..PAGES {
+        unsafe {
+            *page(start, i) = i;
+        }
+    }
+    // 子进程看到 fork 时的内容，它的写入不影响父进程
+    let pid = fork();
+    if pid == 0 {
+        for i in 0..PAGES {
+            unsafe {
+                assert_eq!(*page(start, i), i);
+                *page(start, i) = i + 100;
+            }
+        }
+        unsafe {
+            GLOBAL = 2;
+        }
+        exit(0);
+    }
+    assert_eq!(0, wait_child(pid));
+    for i in 0..PAGES {
+        unsafe {
+            assert_eq!(*page(start, i), i);
+        }
+    }
+    unsafe {
+        assert_eq!(GLOBAL, 1);
+    }
+    // 父进程在 fork 之后的写入同样不影响子进程
+    let pid = fork();
+    if pid == 0 {
+        sleep_blocking(100);
+        for i in 0..PAGES {
+            unsafe {
+                assert_eq!(*page(start, i), i);
+            }
+        }
+        unsafe {
+            assert_eq!(GLOBAL, 1);
+        }
+        exit(0);
+    }
+    for i in 0..PAGES {
+        unsafe {
+            *page(start, i) = i + 200;
+        }
+    }
+    unsafe {
+        GLOBAL = 3;
+    }
+    assert_eq!(0, wait_child(pid));
+    // 子进程退出后只剩父进程一个引用，写入直接进行
+    for i in 0..PAGES {
+        unsafe {
+            assert_eq!(*page(start, i), i + 200);
+            *page(start, i) = i;
+        }
+    }
+    println!("Test copy on write OK!");
+    0
+}
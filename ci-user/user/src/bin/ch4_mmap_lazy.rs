@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, mmap, task_memory, waitpid, MemoryUsage};
+
+/*
+理想结果：输出 Test mmap lazy OK!
+*/
+
+fn mmap_pages() -> usize {
+    let mut usage = MemoryUsage::default();
+    assert_eq!(0, task_memory(&mut usage));
+    usage.mmap
+}
+
+/// 在子进程中执行 `f`，返回子进程的退出码
+fn in_child(f: fn()) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        f();
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(pid, waitpid(pid as usize, &mut exit_code));
+    exit_code
+}
+
+#[no_mangle]
+fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len: usize = 4096 * 16;
+    let prot: usize = 3;
+    let before = mmap_pages();
+    // mmap 只保留地址范围，不分配物理页
+    assert_eq!(start as isize, mmap(start, len, prot));
+    assert_eq!(before, mmap_pages());
+    // 第一次访问时才分配
+    unsafe {
+        *(start as *mut u8) = 1;
+        *((start + 4096 * 5) as *mut u8) = 2;
+    }
+    assert_eq!(before + 2, mmap_pages());
+    // 读一个没碰过的页得到 0
+    unsafe {
+        assert_eq!(*((start + 4096 * 9) as *const u8), 0);
+    }
+    assert_eq!(before + 3, mmap_pages());
+    unsafe {
+        assert_eq!(*(start as *const u8), 1);
+        assert_eq!(*((start + 4096 * 5) as *const u8), 2);
+    }
+    assert_eq!(before + 3, mmap_pages());
+    // 越过映射范围的访问杀死进程
+    let code = in_child(|| unsafe {
+        *((0x10000000 + 4096 * 16) as *mut u8) = 1;
+    });
+    assert_eq!(-2, code);
+    // 违反权限的访问同样杀死进程
+    assert_eq!(0x10100000, mmap(0x10100000, 4096, 1));
+    let code = in_child(|| unsafe {
+        *(0x10100000 as *mut u8) = 1;
+    });
+    assert_eq!(-2, code);
+    println!("Test mmap lazy OK!");
+    0
+}
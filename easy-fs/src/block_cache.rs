@@ -2,15 +2,21 @@ use super::{
     BLOCK_SZ,
     BlockDevice,
 };
+use alloc::boxed::Box;
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use lazy_static::*;
 use spin::Mutex;
 
+/// Data of a cached block, aligned to its size so that an allocator can tell
+/// block buffers from other allocations
+#[repr(C, align(512))]
+struct BlockBuffer([u8; BLOCK_SZ]);
+
 /// Cached block inside memory
 pub struct BlockCache {
     /// cached block data
-    cache: [u8; BLOCK_SZ],
+    cache: Box<BlockBuffer>,
     /// underlying block id
     block_id: usize,
     /// underlying block device
@@ -25,8 +31,8 @@ impl BlockCache {
         block_id: usize,
         block_device: Arc<dyn BlockDevice>
     ) -> Self {
-        let mut cache = [0u8; BLOCK_SZ];
-        block_device.read_block(block_id, &mut cache);
+        let mut cache = Box::new(BlockBuffer([0u8; BLOCK_SZ]));
+        block_device.read_block(block_id, &mut cache.0);
         Self {
             cache,
             block_id,
@@ -36,7 +42,7 @@ impl BlockCache {
     }
     /// Get the address of an offset inside the cached block data
     fn addr_of_offset(&self, offset: usize) -> usize {
-        &self.cache[offset] as *const _ as usize
+        &self.cache.0[offset] as *const _ as usize
     }
 
     pub fn get_ref<T>(&self, offset: usize) -> &T where T: Sized {
@@ -65,7 +71,7 @@ impl BlockCache {
     pub fn sync(&mut self) {
         if self.modified {
             self.modified = false;
-            self.block_device.write_block(self.block_id, &self.cache);
+            self.block_device.write_block(self.block_id, &self.cache.0);
         }
     }
 }
@@ -178,6 +178,15 @@ impl Inode {
             v
         })
     }
+    /// Position of the disk inode, which tells inodes apart
+    pub fn disk_inode_pos(&self) -> (usize, usize) {
+        (self.block_id, self.block_offset)
+    }
+    /// Size of current inode in bytes
+    pub fn size(&self) -> usize {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.size as usize)
+    }
     /// Read data from current inode
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
         let _fs = self.fs.lock();
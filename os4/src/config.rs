@@ -1,18 +1,64 @@
 pub const USER_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 20;
 pub const KERNEL_HEAP_SIZE: usize = 0x30_0000;
+/// the kernel heap grows with frames up to this size
+pub const KERNEL_HEAP_LIMIT: usize = 0x80_0000;
+/// end of memory when the device tree has no memory node
 pub const MEMORY_END: usize = 0x81000000;
+/// the device tree pointer from the SBI is only followed inside this window,
+/// QEMU puts the tree at the top of RAM below 3 GiB
+pub const DTB_WINDOW: (usize, usize) = (0x80000000, 0xc0000000);
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
 pub const MAX_SYSCALL_NUM: usize = 500;
+/// resident pages a task may have, mmap fails with ENOMEM and page faults
+/// kill the task beyond it
+pub const TASK_PAGE_LIMIT: usize = 0x1000;
 
+/// levels of page tables, chosen by the `sv48`/`sv57` features, Sv39 by default
+#[cfg(not(any(feature = "sv48", feature = "sv57")))]
+pub const PAGING_LEVELS: usize = 3;
+#[cfg(all(feature = "sv48", not(feature = "sv57")))]
+pub const PAGING_LEVELS: usize = 4;
+#[cfg(feature = "sv57")]
+pub const PAGING_LEVELS: usize = 5;
+#[cfg(all(feature = "sv48", feature = "sv57"))]
+compile_error!("features `sv48` and `sv57` are exclusive");
+
+// the top pages are canonical in every paging mode
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
-/// Return (bottom, top) of a kernel stack in kernel space.
-pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
-    let top = TRAMPOLINE - app_id * (KERNEL_STACK_SIZE + PAGE_SIZE);
+/// Return (bottom, top) of the kernel stack of process `pid` in kernel space.
+pub fn kernel_stack_position(pid: usize) -> (usize, usize) {
+    let top = TRAMPOLINE - pid * (KERNEL_STACK_SIZE + PAGE_SIZE);
     let bottom = top - KERNEL_STACK_SIZE;
     (bottom, top)
 }
 
+/// timebase frequency when the device tree does not give one
 pub const CLOCK_FREQ: usize = 12500000;
+
+/// virtio slots when the device tree lists none
+pub const MMIO: &[(usize, usize)] = &[
+    (0x10001000, 0x1000),   // virtio-mmio-bus.0, easy-fs image
+    (0x10002000, 0x1000),   // virtio-mmio-bus.1, swap device
+];
+
+/// load address of position independent apps
+pub const PIE_BASE: usize = 0x1_0000;
+/// addresses chosen by the kernel for mmap start here
+pub const MMAP_BASE: usize = 0x10_0000_0000;
+/// with the `aslr` feature, the bases above and the user stack move by up
+/// to this many pages
+pub const ASLR_PAGES: usize = 1 << 16;
+
+/// number of pages the swap device holds
+pub const SWAP_PAGES: usize = 4096;
+/// user pages are swapped out until this many frames are free
+pub const SWAP_WATERMARK: usize = 32;
+/// pass a task of priority 1 would advance by each time it is scheduled
+pub const BIG_STRIDE: usize = 1 << 32;
+/// priorities above are scheduled as this one, whose stride is 1
+pub const MAX_PRIORITY: usize = BIG_STRIDE;
+/// priority of a new task
+pub const DEFAULT_PRIORITY: usize = 16;
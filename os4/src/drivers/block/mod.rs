@@ -0,0 +1,32 @@
+mod virtio_blk;
+
+use crate::fdt::virtio_mmio;
+use lazy_static::*;
+use alloc::sync::Arc;
+use easy_fs::BlockDevice;
+type BlockDeviceImpl = virtio_blk::VirtIOBlock;
+
+/// The virtio block device in MMIO slot `slot`, if there is one.
+fn block_device(slot: usize) -> Option<Arc<dyn BlockDevice>> {
+    let device: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new(virtio_mmio(slot)?)?);
+    Some(device)
+}
+
+lazy_static! {
+    /// block device holding the easy-fs image, on virtio-mmio-bus.0
+    pub static ref BLOCK_DEVICE: Option<Arc<dyn BlockDevice>> = {
+        let device = block_device(0);
+        if device.is_none() {
+            error!("[kernel] no virtio block device for easy-fs, files cannot be opened");
+        }
+        device
+    };
+    /// block device backing the swap area, on virtio-mmio-bus.1
+    pub static ref SWAP_DEVICE: Option<Arc<dyn BlockDevice>> = {
+        let device = block_device(1);
+        if device.is_none() {
+            warn!("[kernel] no virtio block device for swap, swapping is off");
+        }
+        device
+    };
+}
@@ -0,0 +1,82 @@
+use virtio_drivers::{VirtIOBlk, VirtIOHeader};
+use crate::mm::{
+    PhysAddr,
+    VirtAddr,
+    frame_alloc_contiguous,
+    PhysPageNum,
+    FrameTracker,
+    PageTable,
+};
+use easy_fs::BlockDevice;
+use crate::sync::UPSafeCell;
+use alloc::vec::Vec;
+use lazy_static::*;
+use riscv::register::satp;
+
+pub struct VirtIOBlock(UPSafeCell<VirtIOBlk<'static>>);
+
+lazy_static! {
+    static ref QUEUE_FRAMES: UPSafeCell<Vec<FrameTracker>> = unsafe {
+        UPSafeCell::new(Vec::new())
+    };
+}
+
+impl BlockDevice for VirtIOBlock {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        self.0.exclusive_access()
+        .read_block(block_id, buf)
+        .expect("Error when reading VirtIOBlk");
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        self.0.exclusive_access()
+        .write_block(block_id, buf)
+        .expect("Error when writing VirtIOBlk");
+    }
+}
+
+impl VirtIOBlock {
+    /// Probe the virtio block device whose MMIO registers are at `base`,
+    /// `None` if there is none or its queue could not be allocated.
+    pub fn new(base: usize) -> Option<Self> {
+        unsafe {
+            let blk = VirtIOBlk::new(&mut *(base as *mut VirtIOHeader)).ok()?;
+            Some(Self(UPSafeCell::new(blk)))
+        }
+    }
+}
+
+/// Address 0 if frames ran out, which the driver reports as a DMA error.
+#[no_mangle]
+pub extern "C" fn virtio_dma_alloc(pages: usize) -> PhysAddr {
+    let frames = match frame_alloc_contiguous(pages, 1) {
+        Some(frames) => frames,
+        None => return PhysAddr(0),
+    };
+    let ppn_base = frames[0].ppn;
+    QUEUE_FRAMES.exclusive_access().extend(frames);
+    ppn_base.into()
+}
+
+#[no_mangle]
+pub extern "C" fn virtio_dma_dealloc(pa: PhysAddr, pages: usize) -> i32 {
+    let ppn_base: PhysPageNum = pa.into();
+    QUEUE_FRAMES
+        .exclusive_access()
+        .retain(|frame| frame.ppn.0 < ppn_base.0 || frame.ppn.0 >= ppn_base.0 + pages);
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn virtio_phys_to_virt(paddr: PhysAddr) -> VirtAddr {
+    VirtAddr(paddr.0)
+}
+
+/// Translated by the page table in satp, which is the kernel space here,
+/// since `KERNEL_SPACE` may be borrowed by whoever is swapping out pages.
+#[no_mangle]
+pub extern "C" fn virtio_virt_to_phys(vaddr: VirtAddr) -> PhysAddr {
+    match PageTable::from_token(satp::read().bits()).translate_va(vaddr) {
+        Some(paddr) => paddr,
+        None => panic!("virtio buffer at {:#x} is not mapped", vaddr.0),
+    }
+}
@@ -0,0 +1,3 @@
+mod block;
+
+pub use block::{BLOCK_DEVICE, SWAP_DEVICE};
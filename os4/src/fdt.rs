@@ -0,0 +1,313 @@
+//! Flattened device tree passed by the SBI in `a1`
+//!
+//! Only what the kernel needs is picked out of the tree: memory regions, the
+//! number of harts, the timebase frequency, the UART and the virtio MMIO
+//! slots. The tree is parsed before the heap exists, so everything goes into
+//! fixed size arrays. Without a valid tree the values of `config` are used.
+
+use crate::config::{CLOCK_FREQ, DTB_WINDOW, MEMORY_END, MMIO};
+use crate::sync::UPSafeCell;
+use lazy_static::*;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+/// size of the header fields read here
+const FDT_HEADER_SIZE: usize = 40;
+
+const MAX_MEMORY_REGIONS: usize = 4;
+const MAX_VIRTIO: usize = 8;
+const MAX_DEPTH: usize = 16;
+
+/// what the device tree tells about the machine
+#[derive(Copy, Clone)]
+pub struct MachineInfo {
+    /// (base, size) of each memory region
+    memory: [(usize, usize); MAX_MEMORY_REGIONS],
+    memory_count: usize,
+    cpus: usize,
+    timebase_frequency: usize,
+    uart: Option<(usize, usize)>,
+    /// (base, size) of the virtio MMIO slots in address order, which is the
+    /// order of QEMU's virtio-mmio-bus.N
+    virtio: [(usize, usize); MAX_VIRTIO],
+    virtio_count: usize,
+}
+
+impl MachineInfo {
+    /// The machine described by `config`.
+    fn fallback() -> Self {
+        let mut info = Self {
+            memory: [(0, 0); MAX_MEMORY_REGIONS],
+            memory_count: 0,
+            cpus: 1,
+            timebase_frequency: CLOCK_FREQ,
+            uart: None,
+            virtio: [(0, 0); MAX_VIRTIO],
+            virtio_count: 0,
+        };
+        for region in MMIO.iter().take(MAX_VIRTIO) {
+            info.virtio[info.virtio_count] = *region;
+            info.virtio_count += 1;
+        }
+        info
+    }
+}
+
+lazy_static! {
+    static ref MACHINE: UPSafeCell<MachineInfo> = unsafe { UPSafeCell::new(MachineInfo::fallback()) };
+}
+
+/// Properties of a node seen so far, a node's properties come before its
+/// children.
+#[derive(Copy, Clone)]
+struct Node<'a> {
+    name: &'a [u8],
+    compatible: &'a [u8],
+    device_type: &'a [u8],
+    reg: &'a [u8],
+    timebase_frequency: Option<usize>,
+    /// cells of `reg` in the children of this node
+    address_cells: usize,
+    size_cells: usize,
+}
+
+impl<'a> Node<'a> {
+    fn new(name: &'a [u8]) -> Self {
+        Self {
+            name,
+            compatible: &[],
+            device_type: &[],
+            reg: &[],
+            timebase_frequency: None,
+            address_cells: 2,
+            size_cells: 1,
+        }
+    }
+
+    fn is_compatible(&self, model: &[u8]) -> bool {
+        self.compatible.split(|b| *b == 0).any(|item| item == model)
+    }
+}
+
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// A big endian number of `cells` 32-bit cells.
+fn read_cells(data: &[u8], offset: usize, cells: usize) -> Option<usize> {
+    (0..cells).try_fold(0usize, |value, i| {
+        Some(value << 32 | be32(data, offset + i * 4)? as usize)
+    })
+}
+
+/// Bytes up to the NUL at `offset`.
+fn c_str(data: &[u8], offset: usize) -> Option<&[u8]> {
+    let rest = data.get(offset..)?;
+    let len = rest.iter().position(|b| *b == 0)?;
+    Some(&rest[..len])
+}
+
+/// The (base, size) pairs of `node.reg`, read with the cells of `parent`.
+fn reg_pairs<'a>(node: &Node<'a>, parent: &Node<'a>) -> impl Iterator<Item = (usize, usize)> + 'a {
+    let (address_cells, size_cells) = (parent.address_cells, parent.size_cells);
+    let entry = (address_cells + size_cells) * 4;
+    let reg = node.reg;
+    (0..if entry == 0 { 0 } else { reg.len() / entry }).filter_map(move |i| {
+        let base = read_cells(reg, i * entry, address_cells)?;
+        let size = read_cells(reg, i * entry + address_cells * 4, size_cells)?;
+        Some((base, size))
+    })
+}
+
+fn visit(info: &mut MachineInfo, node: &Node, parent: &Node) {
+    if node.device_type == b"memory" {
+        for region in reg_pairs(node, parent) {
+            if info.memory_count < MAX_MEMORY_REGIONS && region.1 > 0 {
+                info.memory[info.memory_count] = region;
+                info.memory_count += 1;
+            }
+        }
+    }
+    if node.device_type == b"cpu" {
+        info.cpus += 1;
+    }
+    if node.name == b"cpus" || node.device_type == b"cpu" {
+        if let Some(frequency) = node.timebase_frequency {
+            info.timebase_frequency = frequency;
+        }
+    }
+    if node.is_compatible(b"ns16550a") {
+        info.uart = reg_pairs(node, parent).next();
+    }
+    if node.is_compatible(b"virtio,mmio") && info.virtio_count < MAX_VIRTIO {
+        if let Some(region) = reg_pairs(node, parent).next() {
+            info.virtio[info.virtio_count] = region;
+            info.virtio_count += 1;
+        }
+    }
+}
+
+/// Whether [start, start + len) lies inside `DTB_WINDOW`.
+fn in_window(start: usize, len: usize) -> bool {
+    start >= DTB_WINDOW.0 && start.checked_add(len).map_or(false, |end| end <= DTB_WINDOW.1)
+}
+
+/// Walk the structure block of the tree at `dtb`. The pointer comes from
+/// the SBI in a register, so it is checked before anything is read, and the
+/// magic before the rest of the header.
+fn parse(dtb: usize) -> Option<MachineInfo> {
+    if dtb == 0 || dtb % 8 != 0 || !in_window(dtb, FDT_HEADER_SIZE) {
+        return None;
+    }
+    let magic = unsafe { core::slice::from_raw_parts(dtb as *const u8, 4) };
+    if be32(magic, 0)? != FDT_MAGIC {
+        return None;
+    }
+    let header = unsafe { core::slice::from_raw_parts(dtb as *const u8, FDT_HEADER_SIZE) };
+    let total_size = be32(header, 4)? as usize;
+    if total_size < FDT_HEADER_SIZE || !in_window(dtb, total_size) {
+        return None;
+    }
+    let data = unsafe { core::slice::from_raw_parts(dtb as *const u8, total_size) };
+    let struct_offset = be32(header, 8)? as usize;
+    let strings_offset = be32(header, 12)? as usize;
+
+    let mut info = MachineInfo {
+        memory: [(0, 0); MAX_MEMORY_REGIONS],
+        memory_count: 0,
+        cpus: 0,
+        timebase_frequency: 0,
+        uart: None,
+        virtio: [(0, 0); MAX_VIRTIO],
+        virtio_count: 0,
+    };
+    // nodes from the root to the current one
+    let mut stack = [Node::new(&[]); MAX_DEPTH];
+    let mut depth = 0;
+    let mut offset = struct_offset;
+    loop {
+        let token = be32(data, offset)?;
+        offset += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = c_str(data, offset)?;
+                offset = (offset + name.len() + 1 + 3) & !3;
+                if depth == MAX_DEPTH {
+                    return None;
+                }
+                // strip the unit address, "memory@80000000" is "memory"
+                let base_name = name.split(|b| *b == b'@').next().unwrap();
+                stack[depth] = Node::new(base_name);
+                depth += 1;
+            }
+            FDT_END_NODE => {
+                if depth == 0 {
+                    return None;
+                }
+                depth -= 1;
+                if depth > 0 {
+                    let (node, parent) = (stack[depth], stack[depth - 1]);
+                    visit(&mut info, &node, &parent);
+                }
+            }
+            FDT_PROP => {
+                let len = be32(data, offset)? as usize;
+                let name = c_str(data, strings_offset + be32(data, offset + 4)? as usize)?;
+                let value = data.get(offset + 8..offset + 8 + len)?;
+                offset = (offset + 8 + len + 3) & !3;
+                if depth == 0 {
+                    return None;
+                }
+                let node = &mut stack[depth - 1];
+                match name {
+                    b"compatible" => node.compatible = value,
+                    b"device_type" => node.device_type = c_str(value, 0).unwrap_or(value),
+                    b"reg" => node.reg = value,
+                    b"#address-cells" => node.address_cells = be32(value, 0)? as usize,
+                    b"#size-cells" => node.size_cells = be32(value, 0)? as usize,
+                    b"timebase-frequency" => {
+                        node.timebase_frequency = read_cells(value, 0, len / 4)
+                    }
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => break,
+            _ => return None,
+        }
+    }
+    info.virtio[..info.virtio_count].sort_unstable();
+    Some(info)
+}
+
+/// Read the device tree at `dtb`, falling back to `config` for whatever it
+/// does not tell. Must be called before the memory manager is set up.
+pub fn init(dtb: usize) {
+    let mut machine = MACHINE.exclusive_access();
+    match parse(dtb) {
+        Some(info) => {
+            if info.memory_count > 0 {
+                machine.memory = info.memory;
+                machine.memory_count = info.memory_count;
+            }
+            if info.cpus > 0 {
+                machine.cpus = info.cpus;
+            }
+            if info.timebase_frequency > 0 {
+                machine.timebase_frequency = info.timebase_frequency;
+            }
+            machine.uart = info.uart;
+            if info.virtio_count > 0 {
+                machine.virtio = info.virtio;
+                machine.virtio_count = info.virtio_count;
+            }
+        }
+        None => warn!("[kernel] no device tree at {:#x}, using the built-in layout", dtb),
+    }
+    for (base, size) in machine.memory[..machine.memory_count].iter() {
+        info!("[kernel] memory [{:#x}, {:#x})", base, base + size);
+    }
+    info!(
+        "[kernel] {} harts, timebase {} Hz, {} virtio slots",
+        machine.cpus, machine.timebase_frequency, machine.virtio_count
+    );
+    if let Some((base, _)) = machine.uart {
+        info!("[kernel] uart at {:#x}", base);
+    }
+}
+
+/// End of the memory region holding the kernel, `MEMORY_END` if the tree
+/// has no memory node. Only this region is given to the frame allocator,
+/// RAM in any other region the tree lists is not used.
+pub fn memory_end() -> usize {
+    extern "C" {
+        fn skernel();
+    }
+    let machine = MACHINE.exclusive_access();
+    machine.memory[..machine.memory_count]
+        .iter()
+        .find(|(base, size)| *base <= skernel as usize && (skernel as usize) < base + size)
+        .map_or(MEMORY_END, |(base, size)| base + size)
+}
+
+/// Frequency of the `time` CSR.
+pub fn clock_freq() -> usize {
+    MACHINE.exclusive_access().timebase_frequency
+}
+
+/// (base, size) of every virtio MMIO slot, in the order of the buses.
+pub fn virtio_regions() -> ([(usize, usize); MAX_VIRTIO], usize) {
+    let machine = MACHINE.exclusive_access();
+    (machine.virtio, machine.virtio_count)
+}
+
+/// Base of the virtio MMIO slot `slot`, `None` if the machine has fewer.
+pub fn virtio_mmio(slot: usize) -> Option<usize> {
+    let machine = MACHINE.exclusive_access();
+    machine.virtio[..machine.virtio_count].get(slot).map(|(base, _)| *base)
+}
@@ -0,0 +1,111 @@
+use super::{File, PageCache};
+use crate::drivers::BLOCK_DEVICE;
+use crate::sync::UPSafeCell;
+use alloc::sync::Arc;
+use easy_fs::{EasyFileSystem, Inode};
+use bitflags::*;
+use lazy_static::*;
+
+/// A wrapper around a filesystem inode
+/// to implement File trait atop
+pub struct OSInode {
+    readable: bool,
+    writable: bool,
+    /// shared with every other open file and mapping of the inode
+    cache: Arc<PageCache>,
+    inner: UPSafeCell<OSInodeInner>,
+}
+
+pub struct OSInodeInner {
+    offset: usize,
+    inode: Arc<Inode>,
+}
+
+impl OSInode {
+    pub fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
+        Self {
+            readable,
+            writable,
+            cache: PageCache::of(&inode),
+            inner: unsafe { UPSafeCell::new(OSInodeInner { offset: 0, inode }) },
+        }
+    }
+}
+
+lazy_static! {
+    /// The root of all inodes, or '/' in short, `None` without a block device
+    pub static ref ROOT_INODE: Option<Arc<Inode>> = BLOCK_DEVICE.clone().map(|device| {
+        let efs = EasyFileSystem::open(device);
+        Arc::new(EasyFileSystem::root_inode(&efs))
+    });
+}
+
+bitflags! {
+    /// Flags for opening files
+    pub struct OpenFlags: u32 {
+        const RDONLY = 0;
+        const WRONLY = 1 << 0;
+        const RDWR = 1 << 1;
+        const CREATE = 1 << 9;
+        const TRUNC = 1 << 10;
+    }
+}
+
+impl OpenFlags {
+    /// Get the current read write permission on an inode
+    /// does not check validity for simplicity
+    /// returns (readable, writable)
+    pub fn read_write(&self) -> (bool, bool) {
+        if self.is_empty() {
+            (true, false)
+        } else if self.contains(Self::WRONLY) {
+            (false, true)
+        } else {
+            (true, true)
+        }
+    }
+}
+
+/// Open a file in the root directory by name
+pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+    let root = ROOT_INODE.as_ref()?;
+    let (readable, writable) = flags.read_write();
+    let inode = match root.find(name) {
+        Some(inode) => {
+            if flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNC) {
+                inode.clear();
+                PageCache::of(&inode).truncate();
+            }
+            inode
+        }
+        None if flags.contains(OpenFlags::CREATE) => root.create(name)?,
+        None => return None,
+    };
+    Some(Arc::new(OSInode::new(readable, writable, inode)))
+}
+
+impl File for OSInode {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, buf: &mut [u8]) -> usize {
+        let mut inner = self.inner.exclusive_access();
+        let read_size = inner.inode.read_at(inner.offset, buf);
+        inner.offset += read_size;
+        read_size
+    }
+    fn write(&self, buf: &[u8]) -> usize {
+        let mut inner = self.inner.exclusive_access();
+        let write_size = inner.inode.write_at(inner.offset, buf);
+        // keep mappings of the file in step with it
+        self.cache.update(inner.offset, &buf[..write_size]);
+        inner.offset += write_size;
+        write_size
+    }
+    fn page_cache(&self) -> Option<Arc<PageCache>> {
+        Some(self.cache.clone())
+    }
+}
@@ -0,0 +1,27 @@
+//! File system in the kernel
+//!
+//! Files are opened from the root directory of the easy-fs image and kept in
+//! the fd table of each task. Regular files can also be mapped into memory
+//! through their [`PageCache`].
+
+mod inode;
+mod page_cache;
+mod stdio;
+
+use alloc::sync::Arc;
+
+/// The common abstraction of all IO resources
+pub trait File: Send + Sync {
+    fn readable(&self) -> bool;
+    fn writable(&self) -> bool;
+    fn read(&self, buf: &mut [u8]) -> usize;
+    fn write(&self, buf: &[u8]) -> usize;
+    /// The page cache of a file which can be mapped with mmap.
+    fn page_cache(&self) -> Option<Arc<PageCache>> {
+        None
+    }
+}
+
+pub use inode::{open_file, OSInode, OpenFlags};
+pub use page_cache::PageCache;
+pub use stdio::{Stdin, Stdout};
@@ -0,0 +1,95 @@
+//! Page cache of easy-fs files
+//!
+//! Every inode opened or mapped has one [`PageCache`], found through its disk
+//! inode position. Pages are read from the inode on first use and stay
+//! cached while the file is open or mapped, so all shared mappings of a file
+//! map the same frames. Dirty pages of shared mappings reach the inode on
+//! `msync` and `munmap`; writes through a file descriptor are copied into the
+//! cached pages. Reads through a file descriptor go to the inode and do not
+//! see mapped writes that have not been synced yet.
+
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_alloc, FrameTracker};
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use easy_fs::Inode;
+use lazy_static::*;
+
+pub struct PageCache {
+    inode: Arc<Inode>,
+    /// cached pages by page index in the file
+    pages: UPSafeCell<BTreeMap<usize, Arc<FrameTracker>>>,
+}
+
+lazy_static! {
+    /// caches by disk inode position, dropped with their last user
+    static ref PAGE_CACHES: UPSafeCell<BTreeMap<(usize, usize), Weak<PageCache>>> = unsafe {
+        UPSafeCell::new(BTreeMap::new())
+    };
+}
+
+impl PageCache {
+    /// The page cache of `inode`, created if nobody uses one.
+    pub fn of(inode: &Arc<Inode>) -> Arc<Self> {
+        let mut caches = PAGE_CACHES.exclusive_access();
+        let key = inode.disk_inode_pos();
+        if let Some(cache) = caches.get(&key).and_then(|cache| cache.upgrade()) {
+            return cache;
+        }
+        caches.retain(|_, cache| cache.strong_count() > 0);
+        let cache = Arc::new(Self {
+            inode: inode.clone(),
+            pages: unsafe { UPSafeCell::new(BTreeMap::new()) },
+        });
+        caches.insert(key, Arc::downgrade(&cache));
+        cache
+    }
+
+    /// Frame holding page `index` of the file, `None` if frames ran out.
+    /// The bytes past the end of the file are zero.
+    pub fn page(&self, index: usize) -> Option<Arc<FrameTracker>> {
+        let mut pages = self.pages.exclusive_access();
+        if let Some(frame) = pages.get(&index) {
+            return Some(frame.clone());
+        }
+        let frame = Arc::new(frame_alloc()?);
+        self.inode.read_at(index * PAGE_SIZE, frame.ppn.get_bytes_array());
+        pages.insert(index, frame.clone());
+        Some(frame)
+    }
+
+    /// Write cached page `index` back to the file, which is not extended.
+    pub fn write_back(&self, index: usize) {
+        let pages = self.pages.exclusive_access();
+        if let Some(frame) = pages.get(&index) {
+            let offset = index * PAGE_SIZE;
+            let len = self.inode.size().saturating_sub(offset).min(PAGE_SIZE);
+            if len > 0 {
+                self.inode.write_at(offset, &frame.ppn.get_bytes_array()[..len]);
+            }
+        }
+    }
+
+    /// Copy `data`, just written to the file at `offset`, into the cached
+    /// pages it covers.
+    pub fn update(&self, offset: usize, data: &[u8]) {
+        let pages = self.pages.exclusive_access();
+        let first = offset / PAGE_SIZE;
+        let last = (offset + data.len() + PAGE_SIZE - 1) / PAGE_SIZE;
+        for (index, frame) in pages.range(first..last) {
+            let page_start = index * PAGE_SIZE;
+            let start = offset.max(page_start);
+            let end = (offset + data.len()).min(page_start + PAGE_SIZE);
+            frame.ppn.get_bytes_array()[start - page_start..end - page_start]
+                .copy_from_slice(&data[start - offset..end - offset]);
+        }
+    }
+
+    /// Zero the cached pages after the file was cleared.
+    pub fn truncate(&self) {
+        for frame in self.pages.exclusive_access().values() {
+            frame.ppn.get_bytes_array().fill(0);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use super::File;
+use crate::sbi::{console_getchar, console_putchar};
+use crate::task::suspend_current_and_run_next;
+
+/// Standard input, read a character at a time from the SBI console
+pub struct Stdin;
+
+/// Standard output
+pub struct Stdout;
+
+impl File for Stdin {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, buf: &mut [u8]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+        // busy loop
+        let c = loop {
+            let c = console_getchar();
+            if c == 0 {
+                suspend_current_and_run_next();
+                continue;
+            }
+            break c;
+        };
+        buf[0] = c as u8;
+        1
+    }
+    fn write(&self, _buf: &[u8]) -> usize {
+        panic!("Cannot write to stdin!");
+    }
+}
+
+impl File for Stdout {
+    fn readable(&self) -> bool {
+        false
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, _buf: &mut [u8]) -> usize {
+        panic!("Cannot read from stdout!");
+    }
+    /// Bytes go out undecoded, so a character split between two writes,
+    /// or two pages of one write, still comes out whole.
+    fn write(&self, buf: &[u8]) -> usize {
+        for byte in buf {
+            console_putchar(*byte as usize);
+        }
+        buf.len()
+    }
+}
@@ -1,4 +1,5 @@
 use crate::sbi::shutdown;
+use crate::task::dump_current_memory_set;
 use core::panic::PanicInfo;
 
 #[panic_handler]
@@ -13,5 +14,6 @@ fn panic(info: &PanicInfo) -> ! {
     } else {
         println!("[kernel] Panicked: {}", info.message().unwrap());
     }
+    dump_current_memory_set();
     shutdown()
 }
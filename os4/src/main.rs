@@ -2,8 +2,8 @@
 #![no_main]
 #![feature(panic_info_message)]
 #![feature(alloc_error_handler)]
+#![feature(allocator_api)]
 
-#[macro_use]
 extern crate bitflags;
 #[macro_use]
 extern crate log;
@@ -13,6 +13,9 @@ extern crate alloc;
 #[macro_use]
 mod console;
 mod config;
+mod drivers;
+mod fdt;
+mod fs;
 mod lang_items;
 mod loader;
 mod logging;
@@ -39,10 +42,13 @@ fn clear_bss() {
 }
 
 #[no_mangle]
-pub fn rust_main() -> ! {
+/// `hart_id` and the device tree address are left in `a0` and `a1` by the SBI
+pub fn rust_main(hart_id: usize, dtb: usize) -> ! {
     clear_bss();
     logging::init();
     println!("[kernel] Hello, world!");
+    info!("[kernel] booting on hart {}", hart_id);
+    fdt::init(dtb);
     mm::init();
     println!("[kernel] back to world!");
     mm::remap_test();
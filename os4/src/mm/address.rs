@@ -1,16 +1,21 @@
 
-use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS};
+use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS, PAGING_LEVELS};
 use super::PageTableEntry;
-const PA_WIDTH_SV39: usize = 56;
-const PPN_WIDTH_SV39: usize = PA_WIDTH_SV39 - PAGE_SIZE_BITS;
-const VA_WIDTH_SV39: usize = 39;
-const VPN_WIDTH_SV39: usize = VA_WIDTH_SV39 - PAGE_SIZE_BITS;
+const PA_WIDTH: usize = 56;
+const PPN_WIDTH: usize = PA_WIDTH - PAGE_SIZE_BITS;
+/// 39, 48 or 57 bits for Sv39, Sv48 and Sv57
+const VA_WIDTH: usize = PAGE_SIZE_BITS + 9 * PAGING_LEVELS;
+const VPN_WIDTH: usize = VA_WIDTH - PAGE_SIZE_BITS;
+/// user addresses are the lower half of the canonical ones
+pub const USER_SPACE_END: usize = 1 << (VA_WIDTH - 1);
 use core::fmt::{self, Debug, Formatter};
 
 
+#[repr(C)]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct PhysAddr(pub usize);
 
+#[repr(C)]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct VirtAddr(pub usize);
 
@@ -43,11 +48,11 @@ impl Debug for PhysPageNum {
 
 // 四个类型和usize的相互转换
 impl From<usize> for PhysAddr {
-    fn from(v: usize) -> Self { Self(v & ( (1 << PA_WIDTH_SV39) -1))}
+    fn from(v: usize) -> Self { Self(v & ( (1 << PA_WIDTH) -1))}
 }
 
 impl From<usize> for PhysPageNum {
-    fn from(v: usize) -> Self { Self(v & ( (1 << PPN_WIDTH_SV39) -1))}
+    fn from(v: usize) -> Self { Self(v & ( (1 << PPN_WIDTH) -1))}
 }
 
 impl From<PhysAddr> for usize {
@@ -59,11 +64,11 @@ impl From<PhysPageNum> for usize {
 }
 
 impl From<usize> for VirtAddr {
-    fn from(v: usize) -> Self { Self(v & ( (1 << VA_WIDTH_SV39) -1))}
+    fn from(v: usize) -> Self { Self(v & ( (1 << VA_WIDTH) -1))}
 }
 
 impl From<usize> for VirtPageNum {
-    fn from(v: usize) -> Self { Self(v & ( (1 << VPN_WIDTH_SV39) -1))}
+    fn from(v: usize) -> Self { Self(v & ( (1 << VPN_WIDTH) -1))}
 }
 
 impl From<VirtAddr> for usize {
@@ -118,13 +123,30 @@ impl VirtAddr {
     pub fn is_align(&self) -> bool {
         self.page_offset() == 0
     }
+    /// The address sign-extended from bit `VA_WIDTH - 1`, as the hardware
+    /// requires it.
+    pub fn canonical(&self) -> usize {
+        let shift = usize::BITS as usize - VA_WIDTH;
+        (((self.0 << shift) as isize) >> shift) as usize
+    }
+    /// Whether all bits above the address width copy its top bit.
+    pub fn is_canonical(v: usize) -> bool {
+        VirtAddr::from(v).canonical() == v
+    }
+    /// Whether `[start, start + len)` is inside the user half.
+    pub fn is_user_range(start: usize, len: usize) -> bool {
+        match start.checked_add(len) {
+            Some(end) => end <= USER_SPACE_END,
+            None => false,
+        }
+    }
 }
 
 impl VirtPageNum {
-    pub fn indexes(&self) -> [usize; 3]{
+    pub fn indexes(&self) -> [usize; PAGING_LEVELS]{
         let mut vpn = self.0;
-        let mut idx = [0usize; 3];
-        for i in(0..3).rev() {
+        let mut idx = [0usize; PAGING_LEVELS];
+        for i in(0..PAGING_LEVELS).rev() {
             idx[i] = vpn & 511;
             vpn >>= 9;
         }
@@ -194,10 +216,9 @@ where
     pub fn get_end(&self) -> T {
         self.r
     }
+    /// Whether the two ranges overlap, an empty range overlaps nothing.
     pub fn includes(&self, vr:Self) -> bool{
-        // println!("self:{:?},{:?},vr:{:?},{:?}",self.l,self.r,vr.l,vr.r);
-        ((self.l<= vr.l) && (vr.l< self.r)) || ((self.l < vr.r) && (vr.r <= self.r)) || (self.l >= vr.l) && (self.r <= vr.r)
-        
+        self.l < vr.r && vr.l < self.r
     }
 }
 impl<T> IntoIterator for SimpleRange<T>
@@ -0,0 +1,101 @@
+//! Address space identifiers
+//!
+//! ASID 0 belongs to the kernel space, user spaces take the others in turn.
+//! When they run out, a new generation starts: the whole TLB is flushed and
+//! every user space gets a new ASID the next time it is activated.
+
+use crate::sync::UPSafeCell;
+use core::arch::asm;
+use lazy_static::*;
+use riscv::register::satp;
+
+const ASID_SHIFT: usize = 44;
+const ASID_MASK: usize = 0xffff;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Asid {
+    generation: usize,
+    pub id: usize,
+}
+
+impl Asid {
+    /// The ASID of the kernel space, which is never recycled.
+    pub const KERNEL: Self = Self {
+        generation: usize::MAX,
+        id: 0,
+    };
+    /// Not assigned yet, a new one is allocated on activation.
+    pub const UNASSIGNED: Self = Self {
+        generation: 0,
+        id: 0,
+    };
+
+    /// The satp ASID field.
+    pub fn bits(&self) -> usize {
+        self.id << ASID_SHIFT
+    }
+}
+
+struct AsidAllocator {
+    generation: usize,
+    next: usize,
+    /// number of ASIDs the hart implements, 1 if it has none
+    max: usize,
+}
+
+impl AsidAllocator {
+    fn alloc(&mut self) -> Asid {
+        if self.max == 1 {
+            // without ASIDs every user space shares 0 and the trampoline
+            // flushes the TLB on each switch
+            return Asid { generation: self.generation, id: 0 };
+        }
+        if self.next == self.max {
+            self.generation += 1;
+            self.next = 1;
+            unsafe {
+                asm!("sfence.vma");
+            }
+        }
+        self.next += 1;
+        Asid {
+            generation: self.generation,
+            id: self.next - 1,
+        }
+    }
+}
+
+lazy_static! {
+    static ref ASID_ALLOCATOR: UPSafeCell<AsidAllocator> = unsafe {
+        UPSafeCell::new(AsidAllocator {
+            generation: 1,
+            next: 1,
+            max: 1,
+        })
+    };
+}
+
+/// Find out how many ASID bits the hart implements by writing all ones to
+/// the field, must be called with the kernel space active.
+pub fn init_asid() {
+    let token = satp::read().bits();
+    satp::write(token | ASID_MASK << ASID_SHIFT);
+    let asid_bits = (satp::read().bits() >> ASID_SHIFT & ASID_MASK).count_ones();
+    unsafe {
+        satp::write(token);
+        asm!("sfence.vma");
+    }
+    ASID_ALLOCATOR.exclusive_access().max = 1 << asid_bits;
+    println!("[kernel] {} ASID bits", asid_bits);
+}
+
+/// Make sure `asid` belongs to the current generation.
+pub fn asid_refresh(asid: &mut Asid) {
+    if *asid == Asid::KERNEL {
+        return;
+    }
+    let mut allocator = ASID_ALLOCATOR.exclusive_access();
+    if asid.generation != allocator.generation {
+        *asid = allocator.alloc();
+    }
+}
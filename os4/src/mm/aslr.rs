@@ -0,0 +1,38 @@
+//! Address space layout randomization
+//!
+//! With the `aslr` feature, the load address of position independent apps,
+//! the user stack and the mmap base move by a random number of pages. The
+//! generator is a xorshift seeded and stirred with the `time` CSR, which is
+//! enough to make the layout unpredictable but is no cryptographic source.
+//! Without the feature every offset is 0, so runs are reproducible.
+
+use crate::sync::UPSafeCell;
+use crate::timer::get_time;
+use lazy_static::*;
+
+lazy_static! {
+    static ref RNG_STATE: UPSafeCell<u64> = unsafe { UPSafeCell::new(0) };
+}
+
+/// Next random number, mixing in the current time.
+fn random() -> u64 {
+    let mut state = RNG_STATE.exclusive_access();
+    let mut x = *state ^ (get_time() as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    if x == 0 {
+        x = 0x2545_f491_4f6c_dd1d;
+    }
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    x
+}
+
+/// A random number of pages below `max_pages`, 0 without ASLR.
+pub fn random_pages(max_pages: usize) -> usize {
+    if cfg!(feature = "aslr") && max_pages > 0 {
+        random() as usize % max_pages
+    } else {
+        0
+    }
+}
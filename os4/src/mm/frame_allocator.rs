@@ -1,6 +1,8 @@
 
 use super::{PhysAddr, PhysPageNum};
-use crate::config::MEMORY_END;
+use crate::config::PAGE_SIZE;
+use crate::fdt::memory_end;
+use crate::task::reclaim_frames;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 use crate::sync::UPSafeCell;
@@ -35,57 +37,194 @@ impl Debug for FrameTracker {
 trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
+    /// Allocate `pages` physically contiguous frames whose first ppn is a
+    /// multiple of `align` (in pages, a power of two).
+    fn alloc_contiguous(&mut self, pages: usize, align: usize) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
+    fn stats(&self) -> FrameStats;
 }
 
-pub struct StackFrameAllocator {
-    current: usize, // 空闲内存的起始物理页号
-    end: usize,     // 空闲内存的结束物理页号
-    recycled: Vec<usize>,
+/// usage of physical frames
+#[derive(Copy, Clone, Debug)]
+pub struct FrameStats {
+    pub total: usize,
+    pub free: usize,
 }
 
+/// blocks hold at most 2^MAX_ORDER frames
+const MAX_ORDER: usize = 20;
+/// empty free list
+const NIL: usize = usize::MAX;
+/// `orders` entry of a frame which does not start a free block
+const NOT_FREE: u8 = u8::MAX;
 
-impl StackFrameAllocator {
+/// link of a free list, stored in the first frame of every free block
+struct FreeNode {
+    prev: usize,
+    next: usize,
+}
+
+/// Buddy system allocator.
+///
+/// A free block of order `k` is 2^k frames aligned to 2^k in physical
+/// memory. The free lists are doubly linked through the free frames
+/// themselves and `orders` records the order of every free block head, so
+/// both alloc and dealloc take O(log n) without touching the kernel heap.
+pub struct BuddyFrameAllocator {
+    /// first and one past the last frame managed
+    base: usize,
+    end: usize,
+    free_lists: [usize; MAX_ORDER + 1],
+    /// one byte per frame in `[base, end)`, kept in the first managed frames
+    orders: usize,
+    free: usize,
+}
+
+impl BuddyFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.current = l.0;
+        let frames = r.0 - l.0;
+        let meta_pages = (frames + PAGE_SIZE - 1) / PAGE_SIZE;
+        self.orders = PhysAddr::from(l).0;
+        self.base = l.0 + meta_pages;
         self.end = r.0;
+        for ppn in self.base..self.end {
+            self.set_order(ppn, NOT_FREE);
+        }
+        // cut the range into the largest aligned blocks
+        let mut ppn = self.base;
+        while ppn < self.end {
+            let mut order = MAX_ORDER;
+            while ppn % (1 << order) != 0 || ppn + (1 << order) > self.end {
+                order -= 1;
+            }
+            self.push(ppn, order);
+            self.free += 1 << order;
+            ppn += 1 << order;
+        }
+    }
+
+    fn order(&self, ppn: usize) -> u8 {
+        unsafe { *(self.orders as *const u8).add(ppn - self.base) }
+    }
+
+    fn set_order(&mut self, ppn: usize, order: u8) {
+        unsafe { *(self.orders as *mut u8).add(ppn - self.base) = order; }
+    }
+
+    fn node(ppn: usize) -> &'static mut FreeNode {
+        PhysPageNum(ppn).get_mut()
+    }
+
+    fn push(&mut self, ppn: usize, order: usize) {
+        let head = self.free_lists[order];
+        *Self::node(ppn) = FreeNode { prev: NIL, next: head };
+        if head != NIL {
+            Self::node(head).prev = ppn;
+        }
+        self.free_lists[order] = ppn;
+        self.set_order(ppn, order as u8);
+    }
+
+    fn remove(&mut self, ppn: usize, order: usize) {
+        let FreeNode { prev, next } = *Self::node(ppn);
+        if prev == NIL {
+            self.free_lists[order] = next;
+        } else {
+            Self::node(prev).next = next;
+        }
+        if next != NIL {
+            Self::node(next).prev = prev;
+        }
+        self.set_order(ppn, NOT_FREE);
+    }
+
+    /// Take a block of exactly `order`, splitting a larger one if needed.
+    fn alloc_order(&mut self, order: usize) -> Option<usize> {
+        let mut k = (order..=MAX_ORDER).find(|k| self.free_lists[*k] != NIL)?;
+        let ppn = self.free_lists[k];
+        self.remove(ppn, k);
+        while k > order {
+            k -= 1;
+            self.push(ppn + (1 << k), k);
+        }
+        self.free -= 1 << order;
+        Some(ppn)
+    }
+
+    /// Whether `ppn` lies in a free block of any order.
+    fn is_free(&self, ppn: usize) -> bool {
+        (0..=MAX_ORDER).any(|k| {
+            let head = ppn & !((1 << k) - 1);
+            head >= self.base && self.order(head) == k as u8
+        })
+    }
+
+    /// Give back the block `[ppn, ppn + 2^order)`, merging it with its buddies.
+    fn dealloc_order(&mut self, mut ppn: usize, mut order: usize) {
+        self.free += 1 << order;
+        while order < MAX_ORDER {
+            let buddy = ppn ^ (1 << order);
+            if buddy < self.base || buddy >= self.end || self.order(buddy) != order as u8 {
+                break;
+            }
+            self.remove(buddy, order);
+            ppn = ppn.min(buddy);
+            order += 1;
+        }
+        self.push(ppn, order);
     }
 }
 
-impl FrameAllocator for StackFrameAllocator {
+impl FrameAllocator for BuddyFrameAllocator {
     fn new() -> Self {
         Self {
-            current: 0,
+            base: 0,
             end: 0,
-            recycled: Vec::new(),
+            free_lists: [NIL; MAX_ORDER + 1],
+            orders: 0,
+            free: 0,
         }
     }
 
     fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
-        } else {
-            if self.current == self.end{
-                None
-            } else {
-                self.current += 1;
-                Some((self.current - 1).into())
-            }
+        self.alloc_order(0).map(|ppn| ppn.into())
+    }
+
+    fn alloc_contiguous(&mut self, pages: usize, align: usize) -> Option<PhysPageNum> {
+        assert!(pages > 0 && align.is_power_of_two());
+        let order = pages.next_power_of_two().max(align).trailing_zeros() as usize;
+        if order > MAX_ORDER {
+            return None;
         }
+        let ppn = self.alloc_order(order)?;
+        // return the unused tail in aligned pieces
+        let mut tail = ppn + pages;
+        let end = ppn + (1 << order);
+        while tail < end {
+            let k = (tail.trailing_zeros() as usize).min((end - tail).trailing_zeros() as usize);
+            self.dealloc_order(tail, k);
+            tail += 1 << k;
+        }
+        Some(ppn.into())
     }
+
     fn dealloc(&mut self, ppn: PhysPageNum) {
         let ppn = ppn.0;
-        if ppn >= self.current || self.recycled
-            .iter()
-            .find(|&v| {*v == ppn})
-            .is_some(){
-            panic!("Frame ppn={:#x} has not been allocated",ppn);
+        if ppn < self.base || ppn >= self.end || self.is_free(ppn) {
+            panic!("Frame ppn={:#x} has not been allocated", ppn);
+        }
+        self.dealloc_order(ppn, 0);
+    }
+
+    fn stats(&self) -> FrameStats {
+        FrameStats {
+            total: self.end - self.base,
+            free: self.free,
         }
-        self.recycled.push(ppn);
     }
 }
 
-type FrameAllocatorImpl = StackFrameAllocator;
+type FrameAllocatorImpl = BuddyFrameAllocator;
 lazy_static! {
     pub static ref FRAME_ALLOCATOR: UPSafeCell<FrameAllocatorImpl> = unsafe{
         UPSafeCell::new(FrameAllocatorImpl::new())
@@ -98,16 +237,56 @@ pub fn init_frame_allocator() {
     }
     FRAME_ALLOCATOR
         .exclusive_access()
-        .init(PhysAddr::from(ekernel as usize).ceil(), PhysAddr::from(MEMORY_END).floor());
+        .init(PhysAddr::from(ekernel as usize).ceil(), PhysAddr::from(memory_end()).floor());
+}
+
+/// Run `alloc` until it succeeds, swapping out user pages after each
+/// failure, `None` once nothing more can be swapped out.
+fn alloc_or_reclaim<T>(mut alloc: impl FnMut() -> Option<T>) -> Option<T> {
+    loop {
+        if let Some(ret) = alloc() {
+            return Some(ret);
+        }
+        if !reclaim_frames() {
+            return None;
+        }
+    }
 }
 
 pub fn frame_alloc() -> Option<FrameTracker> {
-    // println!("1");
+    alloc_or_reclaim(|| FRAME_ALLOCATOR.exclusive_access().alloc()).map(FrameTracker::new)
+}
+
+/// Allocate `pages` contiguous frames aligned to `align` pages.
+pub fn frame_alloc_contiguous(pages: usize, align: usize) -> Option<Vec<FrameTracker>> {
+    let base = alloc_or_reclaim(|| {
+        FRAME_ALLOCATOR
+            .exclusive_access()
+            .alloc_contiguous(pages, align)
+    })?;
+    Some((0..pages).map(|i| FrameTracker::new(PhysPageNum(base.0 + i))).collect())
+}
+
+/// Allocate `pages` contiguous frames aligned to `align` pages without a
+/// `FrameTracker`, used by the kernel heap and the slab caches. No heap
+/// allocation happens on this path. Neither does it reclaim, which
+/// allocates, the heap does that once it has let go of its lock.
+pub fn frame_alloc_pinned(pages: usize, align: usize) -> Option<PhysPageNum> {
     FRAME_ALLOCATOR
         .exclusive_access()
-        .alloc()
-        .map(|ppn| FrameTracker::new(ppn))
-    
+        .alloc_contiguous(pages, align)
+}
+
+/// Give back frames from [`frame_alloc_pinned`].
+pub fn frame_dealloc_pinned(ppn: PhysPageNum, pages: usize) {
+    let mut allocator = FRAME_ALLOCATOR.exclusive_access();
+    for i in 0..pages {
+        allocator.dealloc(PhysPageNum(ppn.0 + i));
+    }
+}
+
+pub fn frame_stats() -> FrameStats {
+    FRAME_ALLOCATOR.exclusive_access().stats()
 }
 
 fn frame_dealloc(ppn: PhysPageNum) {
@@ -119,6 +298,7 @@ fn frame_dealloc(ppn: PhysPageNum) {
 
 #[allow(unused)]
 pub fn frame_allocator_test() {
+    let free = frame_stats().free;
     let mut v: Vec<FrameTracker> = Vec::new();
     for i in 0..5 {
         let frame = frame_alloc().unwrap();
@@ -132,6 +312,13 @@ pub fn frame_allocator_test() {
         v.push(frame);
     }
     drop(v);
+    let frames = frame_alloc_contiguous(5, 4).unwrap();
+    assert_eq!(frames[0].ppn.0 % 4, 0);
+    for (i, frame) in frames.iter().enumerate() {
+        assert_eq!(frame.ppn.0, frames[0].ppn.0 + i);
+    }
+    drop(frames);
+    assert_eq!(frame_stats().free, free);
     println!("frame_allocator_test passed!");
 
 }
\ No newline at end of file
@@ -1,45 +1,174 @@
+use buddy_system_allocator::Heap;
+use crate::config::{KERNEL_HEAP_SIZE, KERNEL_HEAP_LIMIT, PAGE_SIZE};
+use super::frame_allocator::frame_alloc_pinned;
+use super::slab::{slab_alloc, slab_dealloc};
+use super::PhysAddr;
+use crate::task::reclaim_frames;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+use spin::Mutex;
 
-use buddy_system_allocator::LockedHeap;
-use crate::config::KERNEL_HEAP_SIZE;
+/// frames taken from the frame allocator at least, each time the heap grows
+const GROW_PAGES: usize = 16;
+
+/// most times the heap can grow before reaching `KERNEL_HEAP_LIMIT`
+const MAX_REGIONS: usize = (KERNEL_HEAP_LIMIT - KERNEL_HEAP_SIZE) / (GROW_PAGES * PAGE_SIZE);
+
+/// A buddy heap starting in .bss and growing with frames from
+/// `FRAME_ALLOCATOR`, up to `KERNEL_HEAP_LIMIT` bytes in total. Small
+/// objects are served by the slab caches in front of it, and fall through
+/// to the heap when no slab can be had.
+struct GrowableHeap {
+    inner: Mutex<HeapInner>,
+}
+
+struct HeapInner {
+    heap: Heap,
+    /// [start, end) of the memory given to `heap`, the .bss part first
+    regions: [(usize, usize); MAX_REGIONS + 1],
+    region_count: usize,
+}
+
+/// usage of the kernel heap in bytes
+#[derive(Copy, Clone, Debug)]
+pub struct HeapStats {
+    /// bytes requested by allocations
+    pub user: usize,
+    /// bytes taken by allocations, rounded up to buddy blocks
+    pub actual: usize,
+    /// bytes the heap manages
+    pub total: usize,
+}
+
+impl GrowableHeap {
+    const fn empty() -> Self {
+        Self {
+            inner: Mutex::new(HeapInner {
+                heap: Heap::empty(),
+                regions: [(0, 0); MAX_REGIONS + 1],
+                region_count: 0,
+            }),
+        }
+    }
+}
+
+impl HeapInner {
+    /// Give [start, end) to the heap.
+    unsafe fn add_region(&mut self, start: usize, end: usize) {
+        self.heap.add_to_heap(start, end);
+        self.regions[self.region_count] = (start, end);
+        self.region_count += 1;
+    }
+
+    /// Whether `addr` was handed out by the heap rather than a slab.
+    fn owns(&self, addr: usize) -> bool {
+        self.regions[..self.region_count]
+            .iter()
+            .any(|(start, end)| (*start..*end).contains(&addr))
+    }
+
+    /// Add frames to the heap for an allocation of `layout`.
+    fn grow(&mut self, layout: &Layout) -> bool {
+        let needed = layout.size().max(layout.align());
+        let pages = ((needed + PAGE_SIZE - 1) / PAGE_SIZE).next_power_of_two().max(GROW_PAGES);
+        if self.region_count > MAX_REGIONS
+            || self.heap.stats_total_bytes() + pages * PAGE_SIZE > KERNEL_HEAP_LIMIT
+        {
+            return false;
+        }
+        match frame_alloc_pinned(pages, 1) {
+            Some(ppn) => {
+                let start: PhysAddr = ppn.into();
+                unsafe {
+                    self.add_region(start.0, start.0 + pages * PAGE_SIZE);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if let Some(ptr) = slab_alloc(&layout) {
+            return ptr;
+        }
+        let mut inner = self.inner.lock();
+        loop {
+            if let Ok(ptr) = inner.heap.alloc(layout) {
+                return ptr.as_ptr();
+            }
+            if inner.grow(&layout) {
+                continue;
+            }
+            // swapping out pages allocates from this heap
+            drop(inner);
+            if !reclaim_frames() {
+                return core::ptr::null_mut();
+            }
+            inner = self.inner.lock();
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // a slab sized layout may still have come from the heap
+        let owned = self.inner.lock().owns(ptr as usize);
+        if !owned && slab_dealloc(ptr, &layout) {
+            return;
+        }
+        self.inner.lock().heap.dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+}
 
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: GrowableHeap = GrowableHeap::empty();
 
 static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 
 pub fn init_heap(){
     unsafe{
+        let start = HEAP_SPACE.as_ptr() as usize;
         HEAP_ALLOCATOR
+            .inner
             .lock()
-            .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
+            .add_region(start, start + KERNEL_HEAP_SIZE);
+    }
+}
+
+pub fn heap_stats() -> HeapStats {
+    let inner = HEAP_ALLOCATOR.inner.lock();
+    let heap = &inner.heap;
+    HeapStats {
+        user: heap.stats_alloc_user(),
+        actual: heap.stats_alloc_actual(),
+        total: heap.stats_total_bytes(),
     }
 }
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> !{
-    panic!("Heap allocation error, layout = {:?}", layout);
+    panic!("Heap allocation error, layout = {:?}, {:?}", layout, heap_stats());
 }
 
 pub fn heap_test(){
     use alloc::boxed::Box;
     use alloc::vec::Vec;
-    extern "C"{
-        fn sbss();
-        fn ebss();
-    }
-    let bss_range = sbss as usize..ebss as usize;
+    let owned = |addr: usize| HEAP_ALLOCATOR.inner.lock().owns(addr);
+    // small objects come from a slab
     let a = Box::new(5);
     assert_eq!(*a, 5);
-    assert!(bss_range.contains(&(a.as_ref() as *const _ as usize)));
+    assert!(!owned(a.as_ref() as *const _ as usize));
     drop(a);
+    // larger than any size class, so from the buddy heap
     let mut v: Vec<usize> = Vec::new();
     for i in 0..500 {
         v.push(i);
     }
-    for i in 0..500{
-        assert_eq!(v[i],i);
+    for (i, val) in v.iter().enumerate() {
+        assert_eq!(*val, i);
     }
-    assert!(bss_range.contains(&(v.as_ptr() as usize)));
+    assert!(owned(v.as_ptr() as usize));
     drop(v);
     println!("heap_test passed!");
-}
\ No newline at end of file
+}
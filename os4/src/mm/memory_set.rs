@@ -1,12 +1,20 @@
-use super::{PTEFlags, PageTable, PageTableEntry};
-use super::{StepByOne, VPNRange};
+use super::{PTEFlags, PageTable, PageTableEntry, PageSize};
+use super::VPNRange;
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{frame_alloc, FrameTracker};
-use crate::config::{MEMORY_END, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
+use super::swap::{swap_alloc, SwapSlot};
+use super::slab::{SlabBox, MAP_AREA_CACHE};
+use super::aslr::random_pages;
+use super::address::USER_SPACE_END;
+use crate::fs::PageCache;
+use crate::config::{PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
+use crate::config::{ASLR_PAGES, MMAP_BASE, PIE_BASE, TASK_PAGE_LIMIT};
+use crate::fdt::{memory_end, virtio_regions};
 use crate::sync::UPSafeCell;
+use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use core::arch::asm;
-use spin::Mutex;
+use core::cmp::Ordering;
 use bitflags::*;
 use lazy_static::*;
 use alloc::sync::Arc;
@@ -29,6 +37,37 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// flags of mmap, with the values Linux uses
+    pub struct MmapFlags: usize {
+        const SHARED = 0x01;
+        const PRIVATE = 0x02;
+        /// map at exactly `start`, replacing what is there
+        const FIXED = 0x10;
+        const ANONYMOUS = 0x20;
+        /// map at exactly `start`, failing if anything is there
+        const FIXED_NOREPLACE = 0x10_0000;
+    }
+}
+
+/// An area as listed to the user by the maps syscall
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct VmaInfo {
+    pub start_vpn: usize,
+    pub end_vpn: usize,
+    /// `MapType` in declaration order: 0 `Identical`, 1 `Framed`,
+    /// 2 `IdenticalHuge`, 3 `Shared`
+    pub map_type: usize,
+    /// `MapPermission` bits
+    pub perm: usize,
+    /// pages backed by a frame
+    pub resident: usize,
+}
+
+/// returned by mmap when the task would exceed its page limit
+pub const ENOMEM: isize = -12;
+
 extern "C" {
     fn stext();
     fn etext();
@@ -44,21 +83,121 @@ extern "C" {
 
 pub struct MapArea {
     vpn_range: VPNRange, 
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    /// frames are shared with the shm registry and other tasks for `Shared`
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
+    /// pages with a copy in the swap area, which is up to date unless the
+    /// page is also in `data_frames` and dirty
+    swapped: BTreeMap<VirtPageNum, SwapSlot>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// the file mapped by a private `Framed` or a shared `Shared` area
+    file: Option<FileMapping>,
+    kind: AreaKind,
+}
+
+/// What an area holds, for the memory accounting of tasks
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AreaKind {
+    Code,
+    Data,
+    /// the user stack and the trap context
+    Stack,
+    /// the area ending at the program break, never merged with others
+    Heap,
+    /// mmap and shared memory
+    Mmap,
+    Kernel,
+}
+
+/// Resident pages of a user address space, by kind of area
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MemoryUsage {
+    pub code: usize,
+    /// data segments and the heap
+    pub data: usize,
+    pub stack: usize,
+    pub mmap: usize,
+    pub page_table: usize,
+    /// resident pages the task may have at most
+    pub limit: usize,
+}
+
+/// File pages backing an mmap area
+#[derive(Clone)]
+pub struct FileMapping {
+    cache: Arc<PageCache>,
+    /// offset in the file of the first page of the area, page aligned
+    offset: usize,
+    /// whether the fd it was mapped from was open for writing
+    writable: bool,
+}
+
+impl FileMapping {
+    pub fn new(cache: Arc<PageCache>, offset: usize, writable: bool) -> Self {
+        Self { cache, offset, writable }
+    }
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum MapType {
     Identical,  //  恒等映射
     Framed,     // 新分配一个物理页帧与之对应
+    IdenticalHuge,  // 恒等映射，对齐时使用 2MiB/1GiB 大页
+    Shared,     // 映射共享内存段或文件页缓存的物理页帧
+}
+
+/// why an app could not be loaded
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ElfError {
+    /// rejected by the ELF parser
+    Parse(&'static str),
+    /// not a 64-bit RISC-V file
+    WrongMachine,
+    NotExecutable,
+    /// a segment lies outside the file or the user half, or overlaps another
+    BadSegment,
+    /// a relocation other than `R_RISCV_RELATIVE`, or outside the segments
+    BadRelocation,
+    OutOfMemory,
+}
+
+/// program header type giving the permission of the stack
+const PT_GNU_STACK: u32 = 0x6474e551;
+/// dynamic section tags locating the relocation table
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const R_RISCV_NONE: u64 = 0;
+const R_RISCV_RELATIVE: u64 = 3;
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset.checked_add(8)?)?;
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(bytes);
+    Some(u64::from_le_bytes(buf))
+}
+
+/// kind of user access that triggered a page fault
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AccessType {
+    Read,
+    Write,
+    Execute,
 }
 
 
 pub struct MemorySet {
     page_table: PageTable,
-    areas: Vec<MapArea>,
+    areas: Vec<SlabBox<MapArea>>,
+    /// lowest address the kernel picks for mmap
+    mmap_base: usize,
+    /// resident pages of user areas the task may not go beyond
+    page_limit: usize,
+    /// start of the heap, right after the ELF segments
+    heap_bottom: usize,
+    /// the program break, the heap area ends at the page holding it
+    brk: usize,
 }
 
 
@@ -69,35 +208,66 @@ lazy_static! {
 }
 
 impl MemorySet {
-    pub fn new_bare() -> Self {
-        Self {
-            page_table: PageTable::new(),
+    /// An empty address space, `None` if there is no frame for the root
+    /// page table.
+    pub fn new_bare() -> Option<Self> {
+        Some(Self {
+            page_table: PageTable::new()?,
             areas: Vec::new(),
-        }
+            mmap_base: MMAP_BASE,
+            page_limit: TASK_PAGE_LIMIT,
+            heap_bottom: 0,
+            brk: 0,
+        })
     }
-    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
-        if let Some(data) = data {
-            map_area.copy_data(&mut self.page_table, data);
+
+    /// Map and add an area. It is added even if frames ran out halfway, so
+    /// that dropping the address space frees what was mapped.
+    fn push(&mut self, mut map_area: MapArea) -> Result<(), ElfError> {
+        let mapped = map_area.map(&mut self.page_table);
+        self.areas.push(map_area.boxed());
+        if mapped {
+            Ok(())
+        } else {
+            Err(ElfError::OutOfMemory)
         }
-        self.areas.push(map_area);
     }
 
+    /// Map a framed area, `ElfError::OutOfMemory` and nothing mapped if
+    /// frames ran out.
     pub fn insert_frame_area(
         &mut self,
         start_va: VirtAddr, end_va: VirtAddr, permission: MapPermission
-    ) {
-        self.push(MapArea::new(
+    ) -> Result<(), ElfError> {
+        let area = MapArea::new(
             start_va,
             end_va,
             MapType::Framed,
             permission,
-        ), None);
+        );
+        let vr = area.vpn_range;
+        if let Err(err) = self.push(area) {
+            // the kernel space is never dropped to free the area
+            self.remove_area_with_start_vpn(vr.get_start());
+            return Err(err);
+        }
+        self.flush_tlb(vr);
+        Ok(())
+    }
+
+    /// Unmap and remove the area starting at `start_vpn`.
+    pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
+        if let Some(idx) = self.areas.iter().position(|area| area.vpn_range.get_start() == start_vpn) {
+            let mut area = self.areas.remove(idx);
+            area.unmap(&mut self.page_table);
+            self.flush_tlb(area.vpn_range);
+        }
     }
 
     pub fn new_kernel() -> Self {
-        let mut memory_set = Self::new_bare();
-        memory_set.map_trampoline();
+        let mut memory_set = Self::new_bare().expect("out of frames when mapping the kernel");
+        memory_set.page_table.set_kernel_asid();
+        memory_set.map_trampoline().expect("out of frames when mapping the trampoline");
         println!(".text [{:#x}, {:#x})", stext as usize, etext as usize);
         println!(".rodata [{:#x}, {:#x})", srodata as usize, erodata as usize);
         println!(".data [{:#x}, {:#x})", sdata as usize, edata as usize);
@@ -108,92 +278,328 @@ impl MemorySet {
             (etext as usize).into(),
             MapType::Identical,
             MapPermission::R | MapPermission::X,
-        ), None);
+        )).expect("out of frames when mapping the kernel");
         println!("mapping .rodata section");
         memory_set.push(MapArea::new(
             (srodata as usize).into(),
             (erodata as usize).into(),
             MapType::Identical,
             MapPermission::R,
-        ), None);
+        )).expect("out of frames when mapping the kernel");
         println!("mapping .data section");
         memory_set.push(MapArea::new(
             (sdata as usize).into(),
             (edata as usize).into(),
             MapType::Identical,
             MapPermission::R | MapPermission::W,
-        ), None);
+        )).expect("out of frames when mapping the kernel");
         println!("mapping .bss section");
         memory_set.push(MapArea::new(
             (sbss_with_stack as usize).into(),
             (ebss as usize).into(),
             MapType::Identical,
             MapPermission::R | MapPermission::W,
-        ), None);
+        )).expect("out of frames when mapping the kernel");
         println!("mapping physical memory");
         memory_set.push(MapArea::new(
             (ekernel as usize).into(),
-            MEMORY_END.into(),
-            MapType::Identical,
+            memory_end().into(),
+            MapType::IdenticalHuge,
             MapPermission::R | MapPermission::W,
-        ), None);
+        )).expect("out of frames when mapping the kernel");
+        println!("mapping memory-mapped registers");
+        let (regions, count) = virtio_regions();
+        for pair in regions[..count].iter() {
+            memory_set.push(MapArea::new(
+                pair.0.into(),
+                (pair.0 + pair.1).into(),
+                MapType::Identical,
+                MapPermission::R | MapPermission::W,
+            )).expect("out of frames when mapping the kernel");
+        }
         memory_set
     }
     
     
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
-        let mut memory_set = Self::new_bare();
-        memory_set.map_trampoline();
-
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+    /// Build the address space of an app, returning it with the user stack
+    /// top and the entry point.
+    ///
+    /// Segments may start anywhere in a page and share pages with their
+    /// neighbours, a shared page gets the permissions of both. The bytes past
+    /// the file size of a segment are zeroed. A position independent app is
+    /// loaded at `PIE_BASE`, moved by ASLR, and its relative relocations are
+    /// applied.
+    pub fn from_elf(elf_data: &[u8]) -> Result<(Self, usize, usize), ElfError> {
+        let elf = xmas_elf::ElfFile::new(elf_data).map_err(ElfError::Parse)?;
         let elf_header = elf.header;
-        let magic = elf_header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
-        let ph_count = elf_header.pt2.ph_count();
+        if elf_header.pt1.class() != xmas_elf::header::Class::SixtyFour
+            || elf_header.pt2.machine().as_machine() != xmas_elf::header::Machine::RISC_V
+        {
+            return Err(ElfError::WrongMachine);
+        }
+        let bias = match elf_header.pt2.type_().as_type() {
+            xmas_elf::header::Type::Executable => 0,
+            xmas_elf::header::Type::SharedObject => PIE_BASE + random_pages(ASLR_PAGES) * PAGE_SIZE,
+            _ => return Err(ElfError::NotExecutable),
+        };
+        let mut dynamic: Option<&[u8]> = None;
+        let mut segments: Vec<(usize, usize, MapPermission, &[u8])> = Vec::new();
+        let mut stack_perm = MapPermission::R | MapPermission::W | MapPermission::U;
+        for i in 0..elf_header.pt2.ph_count() {
+            let ph = elf.program_header(i).map_err(ElfError::Parse)?;
+            let ph_type = ph.get_type().map_err(ElfError::Parse)?;
+            let mut map_perm = MapPermission::U;
+            let ph_flags = ph.flags();
+            if ph_flags.is_read() { map_perm |= MapPermission::R; }
+            if ph_flags.is_write() { map_perm |= MapPermission::W; }
+            if ph_flags.is_execute() { map_perm |= MapPermission::X; }
+            if ph_type == xmas_elf::program::Type::OsSpecific(PT_GNU_STACK) {
+                stack_perm = map_perm;
+                continue;
+            }
+            if ph_type == xmas_elf::program::Type::Dynamic {
+                let (offset, file_size) = (ph.offset() as usize, ph.file_size() as usize);
+                dynamic = offset.checked_add(file_size).and_then(|end| elf.input.get(offset..end));
+                continue;
+            }
+            if ph_type != xmas_elf::program::Type::Load || ph.mem_size() == 0 {
+                continue;
+            }
+            let start = (ph.virtual_addr() as usize).checked_add(bias).ok_or(ElfError::BadSegment)?;
+            let mem_size = ph.mem_size() as usize;
+            let (offset, file_size) = (ph.offset() as usize, ph.file_size() as usize);
+            if file_size > mem_size || !VirtAddr::is_user_range(start, mem_size) {
+                return Err(ElfError::BadSegment);
+            }
+            let data = offset
+                .checked_add(file_size)
+                .and_then(|end| elf.input.get(offset..end))
+                .ok_or(ElfError::BadSegment)?;
+            segments.push((start, start + mem_size, map_perm, data));
+        }
+        if segments.is_empty() {
+            return Err(ElfError::BadSegment);
+        }
+        segments.sort_by_key(|segment| segment.0);
+        if segments.windows(2).any(|pair| pair[0].1 > pair[1].0) {
+            return Err(ElfError::BadSegment);
+        }
+
+        let mut memory_set = Self::new_bare().ok_or(ElfError::OutOfMemory)?;
+        memory_set.map_trampoline().ok_or(ElfError::OutOfMemory)?;
         let mut max_end_vpn = VirtPageNum(0);
-        for i in 0..ph_count {
-            let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
-                let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
-                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
-                let mut map_perm = MapPermission::U;
-                let ph_flags = ph.flags();
-                if ph_flags.is_read() { map_perm |= MapPermission::R; }
-                if ph_flags.is_write() { map_perm |= MapPermission::W; }
-                if ph_flags.is_execute() { map_perm |= MapPermission::X; }
-                let map_area = MapArea::new(
-                    start_va,
-                    end_va,
-                    MapType::Framed,
-                    map_perm,
-                );
-                max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(
-                    map_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize])
-                );
-            }
-        }
-        // map user stack with U flags
+        for (start, end, map_perm, _) in segments.iter() {
+            let kind = if map_perm.contains(MapPermission::X) { AreaKind::Code } else { AreaKind::Data };
+            let mut map_area = MapArea::new((*start).into(), (*end).into(), MapType::Framed, *map_perm)
+                .with_kind(kind);
+            // the first page is already mapped by the previous segment
+            if map_area.vpn_range.get_start() < max_end_vpn {
+                let shared = map_area.vpn_range.get_start();
+                let last = memory_set.areas.last_mut().unwrap();
+                if last.vpn_range.get_start() < shared {
+                    let tail = last.split_off(shared);
+                    memory_set.areas.push(tail.boxed());
+                }
+                let last = memory_set.areas.last_mut().unwrap();
+                let perm = last.map_perm | *map_perm;
+                last.set_perm(&mut memory_set.page_table, perm);
+                if map_area.vpn_range.get_end() == max_end_vpn {
+                    continue;
+                }
+                map_area = map_area.split_off(max_end_vpn);
+            }
+            max_end_vpn = map_area.vpn_range.get_end();
+            memory_set.push(map_area)?;
+        }
+        for (start, end, _, data) in segments.iter() {
+            memory_set.write_bytes(*start, Some(data), data.len());
+            memory_set.write_bytes(start + data.len(), None, end - start - data.len());
+        }
+        if let Some(dynamic) = dynamic {
+            memory_set.relocate(bias, dynamic, &segments)?;
+        }
+        // the heap starts empty after the segments and grows with brk
         let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_bottom: usize = max_end_va.into();
-        // guard page
-        user_stack_bottom += PAGE_SIZE;
-        let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
-        memory_set.push(MapArea::new(
-            user_stack_bottom.into(),
-            user_stack_top.into(),
-            MapType::Framed,
-            MapPermission::R | MapPermission::W | MapPermission::U,
-        ), None);
+        memory_set.heap_bottom = max_end_va.0;
+        memory_set.brk = max_end_va.0;
+        memory_set.push(
+            MapArea::new(max_end_va, max_end_va, MapType::Framed, MapPermission::R | MapPermission::W | MapPermission::U)
+                .with_kind(AreaKind::Heap),
+        )?;
+        // map user stack with U flags at the top of the user half, leaving
+        // a guard page
+        let user_stack_top = USER_SPACE_END - PAGE_SIZE - random_pages(ASLR_PAGES) * PAGE_SIZE;
+        let user_stack_bottom = user_stack_top - USER_STACK_SIZE;
+        if user_stack_bottom < max_end_va.0 {
+            return Err(ElfError::BadSegment);
+        }
+        memory_set.push(
+            MapArea::new(user_stack_bottom.into(), user_stack_top.into(), MapType::Framed, stack_perm)
+                .with_kind(AreaKind::Stack),
+        )?;
         // map TrapContext
-        memory_set.push(MapArea::new(
-            TRAP_CONTEXT.into(),
-            TRAMPOLINE.into(),
-            MapType::Framed,
-            MapPermission::R | MapPermission::W,
-        ), None);
-        (memory_set, user_stack_top, elf.header.pt2.entry_point() as usize)
+        memory_set.push(
+            MapArea::new(
+                TRAP_CONTEXT.into(),
+                TRAMPOLINE.into(),
+                MapType::Framed,
+                MapPermission::R | MapPermission::W,
+            )
+            .with_kind(AreaKind::Stack),
+        )?;
+        memory_set.mmap_base = MMAP_BASE + random_pages(ASLR_PAGES) * PAGE_SIZE;
+        Ok((memory_set, user_stack_top, elf.header.pt2.entry_point() as usize + bias))
+    }
+
+    /// Copy the address space of a task for `fork`, `None` if frames ran out.
+    ///
+    /// Resident pages of user `Framed` areas are shared copy on write: both
+    /// spaces map the frame without W until one of them writes to it. Pages
+    /// swapped out and the trap context, which the kernel writes through its
+    /// frame, are copied into new frames, pages never touched stay lazy.
+    /// `Shared` areas map the same frames as the parent.
+    pub fn from_existed_user(user_space: &mut MemorySet) -> Option<Self> {
+        let mut memory_set = Self::new_bare()?;
+        memory_set.map_trampoline()?;
+        memory_set.mmap_base = user_space.mmap_base;
+        memory_set.page_limit = user_space.page_limit;
+        memory_set.heap_bottom = user_space.heap_bottom;
+        memory_set.brk = user_space.brk;
+        for area in user_space.areas.iter() {
+            let mut new_area = MapArea {
+                vpn_range: area.vpn_range,
+                data_frames: BTreeMap::new(),
+                swapped: BTreeMap::new(),
+                map_type: area.map_type,
+                map_perm: area.map_perm,
+                file: area.file.clone(),
+                kind: area.kind,
+            };
+            let pte_flags = PTEFlags::from_bits(area.map_perm.bits).unwrap();
+            if area.map_type == MapType::Shared {
+                for (vpn, frame) in area.data_frames.iter() {
+                    if memory_set.page_table.map(*vpn, frame.ppn, pte_flags).is_none() {
+                        memory_set.areas.push(new_area.boxed());
+                        return None;
+                    }
+                    new_area.data_frames.insert(*vpn, frame.clone());
+                }
+                memory_set.areas.push(new_area.boxed());
+                continue;
+            }
+            let mut pages: Vec<VirtPageNum> = Vec::new();
+            if area.map_perm.contains(MapPermission::U) {
+                let cow_flags = pte_flags - PTEFlags::W;
+                for (vpn, frame) in area.data_frames.iter() {
+                    if memory_set.page_table.map(*vpn, frame.ppn, cow_flags).is_none() {
+                        memory_set.areas.push(new_area.boxed());
+                        return None;
+                    }
+                    new_area.data_frames.insert(*vpn, frame.clone());
+                    user_space.page_table.set_flags(*vpn, cow_flags);
+                }
+            } else {
+                pages.extend(area.data_frames.keys());
+            }
+            pages.extend(area.swapped.keys().filter(|vpn| !area.data_frames.contains_key(vpn)));
+            for vpn in pages {
+                let frame = match frame_alloc() {
+                    Some(frame) => frame,
+                    None => {
+                        memory_set.areas.push(new_area.boxed());
+                        return None;
+                    }
+                };
+                match area.data_frames.get(&vpn) {
+                    Some(src) => frame.ppn.get_bytes_array().copy_from_slice(src.ppn.get_bytes_array()),
+                    None => area.swapped[&vpn].read(frame.ppn),
+                }
+                if memory_set.page_table.map(vpn, frame.ppn, pte_flags).is_none() {
+                    memory_set.areas.push(new_area.boxed());
+                    return None;
+                }
+                new_area.data_frames.insert(vpn, Arc::new(frame));
+            }
+            memory_set.areas.push(new_area.boxed());
+        }
+        // the parent may have cached its pages as writable
+        flush_asid(user_space.page_table.asid());
+        Some(memory_set)
+    }
+
+    /// Unmap every area of an exited task, the page table stays until the
+    /// task is reaped. Dirty pages of shared file mappings are written back.
+    pub fn recycle_data_pages(&mut self) {
+        for area in self.areas.iter_mut() {
+            area.unmap(&mut self.page_table);
+        }
+        self.areas.clear();
+    }
+
+    /// Apply the relocations listed in the dynamic section of an app loaded
+    /// `bias` bytes above its link address. Only `R_RISCV_RELATIVE` is
+    /// supported, which is all a static PIE needs.
+    fn relocate(&self, bias: usize, dynamic: &[u8], segments: &[(usize, usize, MapPermission, &[u8])]) -> Result<(), ElfError> {
+        let (mut rela, mut rela_size, mut rela_ent) = (None, 0, 24);
+        for entry in dynamic.chunks_exact(16) {
+            let tag = read_u64(entry, 0).unwrap();
+            let val = read_u64(entry, 8).unwrap() as usize;
+            match tag {
+                DT_RELA => rela = Some(val),
+                DT_RELASZ => rela_size = val,
+                DT_RELAENT => rela_ent = val,
+                _ => {}
+            }
+        }
+        let rela = match rela {
+            Some(rela) => rela + bias,
+            None => return Ok(()),
+        };
+        if rela_ent < 24 {
+            return Err(ElfError::BadRelocation);
+        }
+        // the table is read from the file bytes of the segment holding it
+        let table = segments
+            .iter()
+            .find(|segment| segment.0 <= rela && rela < segment.0 + segment.3.len())
+            .and_then(|segment| segment.3.get(rela - segment.0..))
+            .and_then(|data| data.get(..rela_size))
+            .ok_or(ElfError::BadRelocation)?;
+        for entry in table.chunks_exact(rela_ent) {
+            let offset = read_u64(entry, 0).unwrap() as usize;
+            let info = read_u64(entry, 8).unwrap();
+            let addend = read_u64(entry, 16).unwrap() as usize;
+            match info & 0xffff_ffff {
+                R_RISCV_NONE => {}
+                R_RISCV_RELATIVE => {
+                    let target = offset.wrapping_add(bias);
+                    if !segments.iter().any(|segment| segment.0 <= target && target + 8 <= segment.1) {
+                        return Err(ElfError::BadRelocation);
+                    }
+                    let value = (addend.wrapping_add(bias) as u64).to_le_bytes();
+                    self.write_bytes(target, Some(&value), value.len());
+                }
+                _ => return Err(ElfError::BadRelocation),
+            }
+        }
+        Ok(())
+    }
+
+    /// Write `len` bytes of `data`, or zeros, at user address `va` through
+    /// the page table, whatever the permission of the pages.
+    fn write_bytes(&self, va: usize, data: Option<&[u8]>, len: usize) {
+        let mut done = 0;
+        while done < len {
+            let cur = VirtAddr::from(va + done);
+            let piece = (PAGE_SIZE - cur.page_offset()).min(len - done);
+            let ppn = self.page_table.translate(cur.floor()).unwrap().ppn();
+            let dst = &mut ppn.get_bytes_array()[cur.page_offset()..cur.page_offset() + piece];
+            match data {
+                Some(data) => dst.copy_from_slice(&data[done..done + piece]),
+                None => dst.fill(0),
+            }
+            done += piece;
+        }
     }
 
     pub fn activate(&self) {
@@ -204,50 +610,506 @@ impl MemorySet {
         }
     }
 
-    pub fn map_trampoline(&mut self) {
+    /// Map the trampoline, `None` if frames for the page table ran out.
+    pub fn map_trampoline(&mut self) -> Option<()> {
         self.page_table.map(
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
             PTEFlags::R | PTEFlags::X,
-        );
+        )
     }
 
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
 
-    pub fn token(&self) -> usize {
+    /// satp token of this space, getting it a valid ASID first.
+    pub fn token(&mut self) -> usize {
+        self.page_table.refresh_asid();
         self.page_table.token()
     }
 
+    /// Number of frames used by the page table of this address space.
+    pub fn page_table_frames(&self) -> usize {
+        self.page_table.table_frames()
+    }
+
     pub fn includes(&self, vr: VPNRange) -> bool {
         self.areas.iter().any(|area| area.includes(vr))
     }
 
-    pub fn mmap(&mut self, start: VirtAddr, len: usize, perm: MapPermission) -> isize {
+    /// Map `len` bytes of anonymous memory, or of `file`, and return where.
+    ///
+    /// With `FIXED` or `FIXED_NOREPLACE` the mapping is placed at `start`,
+    /// which must be page aligned; `FIXED` unmaps whatever was there first.
+    /// Otherwise `start` is only a hint: it is used if the range is free, else
+    /// the first gap above the mmap base is taken. A `SHARED` file mapping
+    /// maps the pages of the file cache, a private one gets copies of them.
+    pub fn mmap(
+        &mut self,
+        start: VirtAddr,
+        len: usize,
+        perm: MapPermission,
+        flags: MmapFlags,
+        file: Option<FileMapping>,
+    ) -> isize {
+        let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        let fixed = flags.intersects(MmapFlags::FIXED | MmapFlags::FIXED_NOREPLACE);
+        if pages == 0 || (fixed && !start.is_align()) {
+            return -1;
+        }
+        let start_vpn = if fixed {
+            let vr = VPNRange::new(start.floor(), VirtPageNum(start.floor().0 + pages));
+            // pages replaced by MAP_FIXED are given back
+            let replaced = if flags.contains(MmapFlags::FIXED) { self.resident_in(vr) } else { 0 };
+            if self.resident_pages() - replaced + pages > self.page_limit {
+                return ENOMEM;
+            }
+            if self.includes(vr) {
+                if flags.contains(MmapFlags::FIXED_NOREPLACE) {
+                    return -1;
+                }
+                self.unmap_range(vr);
+            }
+            start.floor()
+        } else {
+            if self.resident_pages() + pages > self.page_limit {
+                return ENOMEM;
+            }
+            match self.find_free(start.floor(), pages) {
+                Some(vpn) => vpn,
+                None => return -1,
+            }
+        };
+        let start: VirtAddr = start_vpn.into();
+        let map_type = if file.is_some() && flags.contains(MmapFlags::SHARED) {
+            MapType::Shared
+        } else {
+            MapType::Framed
+        };
+        // only reserve the range, frames are allocated on first touch
+        let mut area = MapArea::new(start, VirtAddr(start.0 + len), map_type, perm).with_kind(AreaKind::Mmap);
+        area.file = file;
+        self.areas.push(area.boxed());
+        start.0 as isize
+    }
+
+    /// First page of `pages` free pages in the user half, at `hint` if
+    /// possible, otherwise in the lowest gap above the mmap base.
+    fn find_free(&self, hint: VirtPageNum, pages: usize) -> Option<VirtPageNum> {
+        let fits = |vpn: VirtPageNum| {
+            VirtAddr::is_user_range(VirtAddr::from(vpn).0, pages * PAGE_SIZE)
+                && !self.includes(VPNRange::new(vpn, VirtPageNum(vpn.0 + pages)))
+        };
+        if hint.0 != 0 && fits(hint) {
+            return Some(hint);
+        }
+        let mut ranges: Vec<VPNRange> = self.areas.iter().map(|area| area.vpn_range).collect();
+        ranges.sort_by_key(|vr| vr.get_start());
+        let mut candidate = VirtAddr::from(self.mmap_base).floor();
+        for vr in ranges {
+            if vr.get_end() <= candidate {
+                continue;
+            }
+            if vr.get_start().0 >= candidate.0 + pages {
+                break;
+            }
+            candidate = vr.get_end();
+        }
+        if fits(candidate) {
+            Some(candidate)
+        } else {
+            None
+        }
+    }
+
+    /// Move the program break to `addr` and return the new break, or the
+    /// current one if `addr` is 0 or out of reach. The heap area grows and
+    /// shrinks page by page, new pages are backed on first touch.
+    pub fn brk(&mut self, addr: usize) -> usize {
+        if addr < self.heap_bottom || !VirtAddr::is_user_range(addr, 0) {
+            return self.brk;
+        }
+        let heap_start = VirtAddr::from(self.heap_bottom).floor();
+        let idx = match self
+            .areas
+            .iter()
+            .position(|area| area.kind == AreaKind::Heap && area.vpn_range.get_start() == heap_start)
+        {
+            Some(idx) => idx,
+            // the user unmapped the bottom of the heap
+            None => return self.brk,
+        };
+        let old_end = self.areas[idx].vpn_range.get_end();
+        let new_end = VirtAddr::from(addr).ceil();
+        match new_end.cmp(&old_end) {
+            Ordering::Greater => {
+                let vr = VPNRange::new(old_end, new_end);
+                let taken = self
+                    .areas
+                    .iter()
+                    .enumerate()
+                    .any(|(i, area)| i != idx && area.includes(vr));
+                if taken || self.resident_pages() + (new_end.0 - old_end.0) > self.page_limit {
+                    return self.brk;
+                }
+                self.areas[idx].vpn_range = VPNRange::new(heap_start, new_end);
+            }
+            Ordering::Less => {
+                let area = &mut self.areas[idx];
+                for vpn in VPNRange::new(new_end, old_end) {
+                    area.unmap_one(&mut self.page_table, vpn);
+                }
+                area.vpn_range = VPNRange::new(heap_start, new_end);
+                self.flush_tlb(VPNRange::new(new_end, old_end));
+            }
+            Ordering::Equal => {}
+        }
+        self.brk = addr;
+        addr
+    }
+
+    /// Unmap `[start, start + len)`, trimming, splitting or removing every
+    /// area it overlaps. Fails if any page in the range is not mapped.
+    pub fn munmap(&mut self, start: VirtAddr, len: usize) -> isize {
         let end = VirtAddr(start.0 + len);
         let vr = VPNRange::new(start.floor(), end.ceil());
-        if self.includes(vr) {
+        if !self.covers(vr) {
             return -1;
         }
-        self.push(MapArea::new(start, end, MapType::Framed, perm), None);
+        self.unmap_range(vr);
         0
     }
 
-    pub fn munmap(&mut self, start: VirtAddr, len: usize) -> isize {
+    /// Remove every page of `vr` that is mapped.
+    fn unmap_range(&mut self, vr: VPNRange) {
+        self.split_at(vr);
+        let page_table = &mut self.page_table;
+        self.areas.retain_mut(|area| {
+            if area.inside(vr) {
+                area.unmap(page_table);
+                false
+            } else {
+                true
+            }
+        });
+        self.flush_tlb(vr);
+    }
+
+    /// Write the pages of shared file mappings in `[start, start + len)`
+    /// which have been modified back to their files. Fails if any page in
+    /// the range is not mapped.
+    pub fn msync(&mut self, start: VirtAddr, len: usize) -> isize {
         let end = VirtAddr(start.0 + len);
         let vr = VPNRange::new(start.floor(), end.ceil());
-        let pos = self.areas.iter().position(|area| area.match_range(vr));
+        if !self.covers(vr) {
+            return -1;
+        }
+        self.sync_range(vr);
+        0
+    }
+
+    /// Write back every modified page of shared file mappings, used when
+    /// the task exits.
+    pub fn sync_files(&mut self) {
+        self.sync_range(VPNRange::new(VirtPageNum(0), VirtPageNum(USER_SPACE_END / PAGE_SIZE)));
+    }
 
-        match pos {
-            Some(idx) => {
-                self.areas[idx].unmap(&mut self.page_table);
-                self.areas.remove(idx);
-                0
+    fn sync_range(&mut self, vr: VPNRange) {
+        let asid = self.page_table.asid();
+        for area in self.areas.iter().filter(|area| area.map_type == MapType::Shared) {
+            let file = match &area.file {
+                Some(file) => file,
+                None => continue,
+            };
+            for vpn in area.data_frames.range(vr.get_start()..vr.get_end()).map(|(vpn, _)| *vpn) {
+                if self.page_table.clear_dirty(vpn) {
+                    file.cache.write_back(area.file_page(vpn));
+                    // the next write must set the dirty bit again
+                    flush_tlb(VPNRange::new(vpn, VirtPageNum(vpn.0 + 1)), asid, false);
+                }
             }
-            None => -1,
         }
     }
+
+    /// Change the permission of `[start, start + len)` to `perm`, splitting
+    /// the areas at the range bounds and merging neighbours afterwards.
+    pub fn mprotect(&mut self, start: VirtAddr, len: usize, perm: MapPermission) -> isize {
+        let end = VirtAddr(start.0 + len);
+        let vr = VPNRange::new(start.floor(), end.ceil());
+        if !self.covers(vr) {
+            return -1;
+        }
+        // writes to a shared file mapping reach the file, as in sys_mmap
+        if perm.contains(MapPermission::W)
+            && self.areas.iter().any(|area| area.includes(vr) && !area.may_write_file())
+        {
+            return -1;
+        }
+        self.split_at(vr);
+        for area in self.areas.iter_mut().filter(|area| area.inside(vr)) {
+            area.set_perm(&mut self.page_table, perm);
+        }
+        self.flush_tlb(vr);
+        self.merge_areas();
+        0
+    }
+
+    /// Flush the TLB entries of the pages in `vr` tagged with our ASID.
+    fn flush_tlb(&mut self, vr: VPNRange) {
+        let tables_freed = self.page_table.take_tables_freed();
+        flush_tlb(vr, self.page_table.asid(), tables_freed);
+    }
+
+    /// Whether `vr` is not empty and every page of it belongs to a user area.
+    fn covers(&self, vr: VPNRange) -> bool {
+        vr.get_start() != vr.get_end() && self.mapped_in(vr) == vr.get_end().0 - vr.get_start().0
+    }
+
+    /// Number of pages of `vr` in user areas.
+    fn mapped_in(&self, vr: VPNRange) -> usize {
+        self.areas
+            .iter()
+            .filter(|area| area.map_perm.contains(MapPermission::U))
+            .map(|area| {
+                let l = area.vpn_range.get_start().max(vr.get_start());
+                let r = area.vpn_range.get_end().min(vr.get_end());
+                r.0.saturating_sub(l.0)
+            })
+            .sum()
+    }
+
+    /// Number of resident pages in the areas of the task, counted against
+    /// its limit. A frame shared with other tasks counts for each of them.
+    fn resident_pages(&self) -> usize {
+        self.areas
+            .iter()
+            .filter(|area| area.kind != AreaKind::Kernel)
+            .map(|area| area.data_frames.len())
+            .sum()
+    }
+
+    /// Number of resident pages of the task in `vr`.
+    fn resident_in(&self, vr: VPNRange) -> usize {
+        self.areas
+            .iter()
+            .filter(|area| area.kind != AreaKind::Kernel)
+            .map(|area| area.data_frames.range(vr.get_start()..vr.get_end()).count())
+            .sum()
+    }
+
+    /// The areas in address order, for the maps syscall.
+    pub fn vmas(&self) -> Vec<VmaInfo> {
+        let mut vmas: Vec<VmaInfo> = self
+            .areas
+            .iter()
+            .map(|area| VmaInfo {
+                start_vpn: area.vpn_range.get_start().0,
+                end_vpn: area.vpn_range.get_end().0,
+                map_type: area.map_type as usize,
+                perm: area.map_perm.bits as usize,
+                resident: area.data_frames.len(),
+            })
+            .collect();
+        vmas.sort_by_key(|vma| vma.start_vpn);
+        vmas
+    }
+
+    /// Print the areas, like /proc/self/maps.
+    pub fn dump(&self) {
+        let mut order: Vec<&MapArea> = self.areas.iter().map(|area| &**area).collect();
+        order.sort_by_key(|area| area.vpn_range.get_start());
+        println!("[kernel] {} areas:", order.len());
+        for area in order {
+            let start: VirtAddr = area.vpn_range.get_start().into();
+            let end: VirtAddr = area.vpn_range.get_end().into();
+            let perm = area.map_perm;
+            println!(
+                "  [{:#x}, {:#x}) {}{}{}{} {:?} {:?}, {} resident, {} swapped{}",
+                start.0,
+                end.0,
+                if perm.contains(MapPermission::R) { 'r' } else { '-' },
+                if perm.contains(MapPermission::W) { 'w' } else { '-' },
+                if perm.contains(MapPermission::X) { 'x' } else { '-' },
+                if perm.contains(MapPermission::U) { 'u' } else { '-' },
+                area.map_type,
+                area.kind,
+                area.data_frames.len(),
+                area.swapped.len(),
+                if area.file.is_some() { ", file" } else { "" },
+            );
+        }
+    }
+
+    /// Resident pages of the task by kind of area. A frame shared with other
+    /// tasks counts for each of them.
+    pub fn usage(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage {
+            page_table: self.page_table_frames(),
+            limit: self.page_limit,
+            ..Default::default()
+        };
+        for area in self.areas.iter() {
+            let resident = area.data_frames.len();
+            match area.kind {
+                AreaKind::Code => usage.code += resident,
+                AreaKind::Data | AreaKind::Heap => usage.data += resident,
+                AreaKind::Stack => usage.stack += resident,
+                AreaKind::Mmap => usage.mmap += resident,
+                AreaKind::Kernel => {}
+            }
+        }
+        usage
+    }
+
+    /// Split the areas crossing the bounds of `vr`, so that every area is
+    /// either inside or outside of it.
+    fn split_at(&mut self, vr: VPNRange) {
+        for vpn in [vr.get_start(), vr.get_end()] {
+            if let Some(idx) = self.areas.iter().position(|area| {
+                area.vpn_range.get_start() < vpn && vpn < area.vpn_range.get_end()
+            }) {
+                let tail = self.areas[idx].split_off(vpn);
+                self.areas.insert(idx + 1, tail.boxed());
+            }
+        }
+    }
+
+    /// Merge adjacent user areas which have the same type and permission.
+    fn merge_areas(&mut self) {
+        self.areas.sort_by_key(|area| area.vpn_range.get_start());
+        let mut merged: Vec<SlabBox<MapArea>> = Vec::with_capacity(self.areas.len());
+        for area in self.areas.drain(..) {
+            if let Some(last) = merged.last_mut() {
+                if last.can_merge(&area) {
+                    last.merge(*area);
+                    continue;
+                }
+            }
+            merged.push(area);
+        }
+        self.areas = merged;
+    }
+
+    /// Resolve a user page fault at `va`.
+    ///
+    /// A page of a `Framed` area that has not been touched yet is backed by a
+    /// new frame here, a swapped out page is read back, and a write to a page
+    /// shared copy on write gets a private copy. Returns false if
+    /// `va` is not inside any area, the access is not allowed by the area's
+    /// `MapPermission`, or a new frame would take the task over its limit.
+    pub fn handle_page_fault(&mut self, va: VirtAddr, access: AccessType) -> bool {
+        let vpn = va.floor();
+        let room = self.resident_pages() < self.page_limit;
+        let area = match self.areas.iter_mut().find(|area| area.contains(vpn)) {
+            Some(area) => area,
+            None => return false,
+        };
+        if !area.permits(access) {
+            return false;
+        }
+        let pte = self.page_table.translate(vpn).filter(|pte| pte.is_valid());
+        match pte {
+            None => {
+                if !room || !area.populate(&mut self.page_table, vpn) {
+                    return false;
+                }
+            }
+            // the area allows writes, so the page is shared copy on write
+            Some(pte) if access == AccessType::Write && !pte.writable() => {
+                // a private copy takes a new frame, the last sharer needs none
+                if (!room && area.shares_frame(vpn)) || !area.copy_on_write(&mut self.page_table, vpn) {
+                    return false;
+                }
+            }
+            Some(_) => {}
+        }
+        self.page_table.mark_accessed(vpn, access == AccessType::Write);
+        // drop a stale entry which could make the access fault again
+        self.flush_tlb(VPNRange::new(vpn, VirtPageNum(vpn.0 + 1)));
+        true
+    }
+
+    /// Translate `va` for a kernel access on behalf of the user, faulting the
+    /// page in first. Returns `None` if the user itself could not do `access`.
+    pub fn translate_user(&mut self, va: VirtAddr, access: AccessType) -> Option<PhysAddr> {
+        if !self.handle_page_fault(va, access) {
+            return None;
+        }
+        let pa: PhysAddr = self.page_table.translate(va.floor())?.ppn().into();
+        Some(PhysAddr(pa.0 + va.page_offset()))
+    }
+
+    /// Map the frames of a shared memory segment at `start`.
+    pub fn attach_shared(&mut self, start: VirtAddr, frames: &[Arc<FrameTracker>], perm: MapPermission) -> isize {
+        if !VirtAddr::is_user_range(start.0, frames.len() * PAGE_SIZE) {
+            return -1;
+        }
+        let end = VirtAddr(start.0 + frames.len() * PAGE_SIZE);
+        let vr = VPNRange::new(start.floor(), end.ceil());
+        if self.includes(vr) {
+            return -1;
+        }
+        if self.resident_pages() + frames.len() > self.page_limit {
+            return ENOMEM;
+        }
+        let mut area = MapArea::new(start, end, MapType::Shared, perm).with_kind(AreaKind::Mmap);
+        for (vpn, frame) in vr.into_iter().zip(frames.iter()) {
+            area.data_frames.insert(vpn, frame.clone());
+        }
+        if self.push(area).is_err() {
+            let mut area = self.areas.pop().unwrap();
+            area.unmap(&mut self.page_table);
+            self.flush_tlb(vr);
+            return ENOMEM;
+        }
+        0
+    }
+
+    /// Unmap the shared memory area starting at `start`.
+    pub fn detach_shared(&mut self, start: VirtAddr) -> isize {
+        let idx = match self.areas.iter().position(|area| {
+            area.map_type == MapType::Shared
+                && area.file.is_none()
+                && area.vpn_range.get_start() == start.floor()
+        }) {
+            Some(idx) => idx,
+            None => return -1,
+        };
+        let mut area = self.areas.remove(idx);
+        area.unmap(&mut self.page_table);
+        self.flush_tlb(area.vpn_range);
+        0
+    }
+
+    /// Swap out one user page at or after `from` with the clock policy.
+    ///
+    /// Pages accessed since the last pass lose their accessed bit and get a
+    /// second chance. Returns the evicted page, or `None` once the hand
+    /// reaches the end of the address space or the swap area is full.
+    pub fn swap_out(&mut self, from: VirtPageNum) -> Option<VirtPageNum> {
+        let mut order: Vec<usize> = (0..self.areas.len())
+            .filter(|i| self.areas[*i].swappable())
+            .collect();
+        order.sort_by_key(|i| self.areas[*i].vpn_range.get_start());
+        for i in order {
+            let area = &mut self.areas[i];
+            let resident: Vec<VirtPageNum> = area.data_frames.range(from..).map(|(vpn, _)| *vpn).collect();
+            for vpn in resident {
+                let vr = VPNRange::new(vpn, VirtPageNum(vpn.0 + 1));
+                if self.page_table.clear_accessed(vpn) {
+                    flush_tlb(vr, self.page_table.asid(), self.page_table.take_tables_freed());
+                    continue;
+                }
+                if !area.swap_out(&mut self.page_table, vpn) {
+                    return None;
+                }
+                flush_tlb(vr, self.page_table.asid(), self.page_table.take_tables_freed());
+                return Some(vpn);
+            }
+        }
+        None
+    }
 }
 
 
@@ -259,96 +1121,339 @@ impl MapArea {
             Self {
                 vpn_range: VPNRange::new(start_vpn, end_vpn),
                 data_frames: BTreeMap::new(),
+                swapped: BTreeMap::new(),
                 map_type,
                 map_perm,
+                file: None,
+                kind: AreaKind::Kernel,
             }
     }
 
-    pub fn map(&mut self, page_table: &mut PageTable) {
+    /// Move the area into its slab cache, as `MemorySet` keeps it.
+    fn boxed(self) -> SlabBox<Self> {
+        Box::new_in(self, &MAP_AREA_CACHE)
+    }
+
+    /// Tag the area with what it holds, areas are `Kernel` by default.
+    pub fn with_kind(mut self, kind: AreaKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Map every page, returns false if frames ran out.
+    pub fn map(&mut self, page_table: &mut PageTable) -> bool {
+        if self.map_type == MapType::IdenticalHuge {
+            let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+            return self
+                .huge_pages()
+                .into_iter()
+                .all(|(vpn, size)| page_table.map_huge(vpn, PhysPageNum(vpn.0), pte_flags, size).is_some());
+        }
         for vpn in self.vpn_range {
-            self.map_one(page_table, vpn)
+            if !self.map_one(page_table, vpn) {
+                // pages from `vpn` on are not mapped, keep unmap away from them
+                self.data_frames.split_off(&vpn);
+                return false;
+            }
         }
+        true
     }
 
     pub fn unmap(&mut self, page_table: &mut PageTable) {
+        if self.map_type == MapType::IdenticalHuge {
+            for (vpn, _) in self.huge_pages() {
+                page_table.unmap(vpn);
+            }
+            return;
+        }
         for vpn in self.vpn_range {
             self.unmap_one(page_table, vpn);
         }
     }
 
-    pub fn copy_data(&mut self, page_table: &mut PageTable, data:&[u8]) {
-        assert_eq!(self.map_type, MapType::Framed);
-        let mut start: usize = 0;
-        let mut current_vpn = self.vpn_range.get_start();
-        let len = data.len();
-        loop {
-            let src = &data[start..len.min(start+PAGE_SIZE)];
-            let dst = &mut page_table
-                .translate(current_vpn)
-                .unwrap()
-                .ppn()
-                .get_bytes_array()[..src.len()];
-            dst.copy_from_slice(src);
-            start += PAGE_SIZE;
-            if start >= len {
-                break;
-            }
-            current_vpn.step();
+    /// Cut the range into the largest pages its alignment allows.
+    fn huge_pages(&self) -> Vec<(VirtPageNum, PageSize)> {
+        let mut pages = Vec::new();
+        let mut vpn = self.vpn_range.get_start();
+        let end = self.vpn_range.get_end();
+        while vpn < end {
+            let size = [PageSize::Size1G, PageSize::Size2M, PageSize::Size4K]
+                .iter()
+                .copied()
+                .find(|size| vpn.0 % size.pages() == 0 && vpn.0 + size.pages() <= end.0)
+                .unwrap();
+            pages.push((vpn, size));
+            vpn = VirtPageNum(vpn.0 + size.pages());
         }
+        pages
     }
 
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        let ppn: PhysPageNum;
+    /// Map the page at `vpn`, returns false if frames ran out.
+    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
         match self.map_type {
-            MapType::Identical => {
-                ppn = PhysPageNum(vpn.0);
+            MapType::Identical | MapType::IdenticalHuge => {
+                page_table.map(vpn, PhysPageNum(vpn.0), pte_flags).is_some()
             }
             MapType::Framed => {
-                let frame =frame_alloc().unwrap();
-                ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                let frame = match frame_alloc() {
+                    Some(frame) => frame,
+                    None => return false,
+                };
+                if page_table.map(vpn, frame.ppn, pte_flags).is_none() {
+                    return false;
+                }
+                self.data_frames.insert(vpn, Arc::new(frame));
+                true
+            }
+            MapType::Shared => {
+                page_table.map(vpn, self.data_frames[&vpn].ppn, pte_flags).is_some()
             }
         }
-        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags)
     }
 
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         match self.map_type {
             MapType::Framed => {
-                self.data_frames.remove(&vpn);
+                self.swapped.remove(&vpn);
+                // never touched or swapped out, so there is nothing in the page table
+                if self.data_frames.remove(&vpn).is_none() {
+                    return;
+                }
+            }
+            MapType::Shared => {
+                // pages of a file are mapped on first touch
+                if self.data_frames.remove(&vpn).is_none() {
+                    return;
+                }
+                if let Some(file) = &self.file {
+                    if page_table.translate(vpn).unwrap().dirty() {
+                        file.cache.write_back(self.file_page(vpn));
+                    }
+                }
             }
             _ => {}
         }
         page_table.unmap(vpn);
     }
 
+    /// Index in the mapped file of the page at `vpn`.
+    fn file_page(&self, vpn: VirtPageNum) -> usize {
+        let offset = self.file.as_ref().unwrap().offset;
+        offset / PAGE_SIZE + (vpn.0 - self.vpn_range.get_start().0)
+    }
+
+    /// Back `vpn` with a frame, used for lazily mapped `Framed` areas and
+    /// shared file mappings.
+    ///
+    /// A new frame is read from the swap area if the page was swapped out,
+    /// or copied from the file of a private file mapping. A shared file
+    /// mapping maps the page of the file cache itself.
+    pub fn populate(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let frame = match (self.map_type, &self.file) {
+            (MapType::Framed, _) => {
+                let frame = match frame_alloc() {
+                    Some(frame) => frame,
+                    None => return false,
+                };
+                if let Some(slot) = self.swapped.get(&vpn) {
+                    slot.read(frame.ppn);
+                } else if let Some(file) = &self.file {
+                    match file.cache.page(self.file_page(vpn)) {
+                        Some(page) => frame.ppn.get_bytes_array().copy_from_slice(page.ppn.get_bytes_array()),
+                        None => return false,
+                    }
+                }
+                Arc::new(frame)
+            }
+            (MapType::Shared, Some(file)) => match file.cache.page(self.file_page(vpn)) {
+                Some(page) => page,
+                None => return false,
+            },
+            _ => return false,
+        };
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        if page_table.map(vpn, frame.ppn, pte_flags).is_none() {
+            return false;
+        }
+        self.data_frames.insert(vpn, frame);
+        true
+    }
+
+    /// Whether the frame at `vpn` is also mapped by another task.
+    pub fn shares_frame(&self, vpn: VirtPageNum) -> bool {
+        Arc::strong_count(&self.data_frames[&vpn]) > 1
+    }
+
+    /// Give the task writing to a page shared copy on write a frame of its
+    /// own. The last task mapping the frame just gets W back. Returns false
+    /// if frames ran out.
+    pub fn copy_on_write(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let frame = self.data_frames.get_mut(&vpn).unwrap();
+        if Arc::strong_count(frame) > 1 {
+            let copy = match frame_alloc() {
+                Some(copy) => copy,
+                None => return false,
+            };
+            copy.ppn.get_bytes_array().copy_from_slice(frame.ppn.get_bytes_array());
+            *frame = Arc::new(copy);
+        }
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        page_table.remap(vpn, frame.ppn, pte_flags);
+        true
+    }
+
+    /// Write a resident page to the swap area and release its frame.
+    /// A clean page which already has a copy there is not written again.
+    /// Returns false if the swap area is full.
+    pub fn swap_out(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let dirty = page_table.translate(vpn).unwrap().dirty();
+        if dirty || !self.swapped.contains_key(&vpn) {
+            let slot = match self.swapped.remove(&vpn) {
+                Some(slot) => slot,
+                None => match swap_alloc() {
+                    Some(slot) => slot,
+                    None => return false,
+                },
+            };
+            slot.write(self.data_frames[&vpn].ppn);
+            self.swapped.insert(vpn, slot);
+        }
+        self.data_frames.remove(&vpn);
+        page_table.unmap(vpn);
+        true
+    }
+
+    /// Only user pages of `Framed` areas may be swapped out, the kernel
+    /// accesses the others through their physical address.
+    pub fn swappable(&self) -> bool {
+        self.map_type == MapType::Framed && self.map_perm.contains(MapPermission::U)
+    }
+
+    pub fn contains(&self, vpn: VirtPageNum) -> bool {
+        self.vpn_range.get_start() <= vpn && vpn < self.vpn_range.get_end()
+    }
+
+    /// Whether a user `access` is allowed by this area.
+    /// W without R is a reserved encoding in RISC-V, so it never permits writes.
+    pub fn permits(&self, access: AccessType) -> bool {
+        if !self.map_perm.contains(MapPermission::U) {
+            return false;
+        }
+        match access {
+            AccessType::Read => self.map_perm.contains(MapPermission::R),
+            AccessType::Write => self.map_perm.contains(MapPermission::R | MapPermission::W),
+            AccessType::Execute => self.map_perm.contains(MapPermission::X),
+        }
+    }
+
     pub fn includes(&self, vr: VPNRange) -> bool {
         self.vpn_range.includes(vr)
     }
 
-    pub fn match_range(&self, vr: VPNRange) -> bool {
-        self.vpn_range.get_start() == vr.get_start() && self.vpn_range.get_end() == vr.get_end()
+    /// False for a shared mapping of a file that was not opened for writing.
+    pub fn may_write_file(&self) -> bool {
+        match &self.file {
+            Some(file) if self.map_type == MapType::Shared => file.writable,
+            _ => true,
+        }
+    }
+
+    /// Whether the area lies entirely inside `vr`.
+    pub fn inside(&self, vr: VPNRange) -> bool {
+        vr.get_start() <= self.vpn_range.get_start() && self.vpn_range.get_end() <= vr.get_end()
+    }
+
+    /// Split the area at `vpn`: `self` keeps `[start, vpn)` and the returned
+    /// area takes `[vpn, end)` together with its frames.
+    pub fn split_off(&mut self, vpn: VirtPageNum) -> Self {
+        let file = self.file.as_ref().map(|file| FileMapping {
+            cache: file.cache.clone(),
+            offset: file.offset + (vpn.0 - self.vpn_range.get_start().0) * PAGE_SIZE,
+            writable: file.writable,
+        });
+        let tail = Self {
+            vpn_range: VPNRange::new(vpn, self.vpn_range.get_end()),
+            data_frames: self.data_frames.split_off(&vpn),
+            swapped: self.swapped.split_off(&vpn),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            file,
+            kind: self.kind,
+        };
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), vpn);
+        tail
+    }
+
+    pub fn can_merge(&self, next: &Self) -> bool {
+        self.vpn_range.get_end() == next.vpn_range.get_start()
+            && self.map_type == MapType::Framed
+            && next.map_type == MapType::Framed
+            && self.map_perm == next.map_perm
+            && self.map_perm.contains(MapPermission::U)
+            && self.file.is_none()
+            && next.file.is_none()
+            && self.kind == next.kind
+    }
+
+    /// Append `next`, which must directly follow `self`.
+    pub fn merge(&mut self, mut next: Self) {
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), next.vpn_range.get_end());
+        self.data_frames.append(&mut next.data_frames);
+        self.swapped.append(&mut next.swapped);
+    }
+
+    /// Change the permission and rewrite the PTE of every backed page.
+    /// Pages shared copy on write stay without W.
+    pub fn set_perm(&mut self, page_table: &mut PageTable, perm: MapPermission) {
+        self.map_perm = perm;
+        let pte_flags = PTEFlags::from_bits(perm.bits).unwrap();
+        for (vpn, frame) in self.data_frames.iter() {
+            if self.map_type == MapType::Framed && Arc::strong_count(frame) > 1 {
+                page_table.set_flags(*vpn, pte_flags - PTEFlags::W);
+            } else {
+                page_table.set_flags(*vpn, pte_flags);
+            }
+        }
     }
 
 }
 
+/// Flush the TLB entries of the pages in `vr` for address space `asid`.
+/// Entries of intermediate tables are only dropped by flushing the whole
+/// address space, so that is done if one of them was freed.
+fn flush_tlb(vr: VPNRange, asid: usize, tables_freed: bool) {
+    if tables_freed {
+        flush_asid(asid);
+        return;
+    }
+    for vpn in vr {
+        let va: VirtAddr = vpn.into();
+        unsafe {
+            asm!("sfence.vma {}, {}", in(reg) va.0, in(reg) asid);
+        }
+    }
+}
+
+/// Flush every TLB entry of address space `asid`.
+fn flush_asid(asid: usize) {
+    unsafe {
+        asm!("sfence.vma zero, {}", in(reg) asid);
+    }
+}
+
 pub fn remap_test() {
-    let mut kernel_space = KERNEL_SPACE.exclusive_access();
+    let kernel_space = KERNEL_SPACE.exclusive_access();
     let mid_text: VirtAddr = ((stext as usize + etext as usize) / 2).into();
     let mid_rodata: VirtAddr = ((srodata as usize + erodata as usize) / 2).into();
     let mid_data: VirtAddr = ((sdata as usize + edata as usize) / 2).into();
+    assert!(!kernel_space.page_table.translate(mid_text.floor()).unwrap().writable());
+    assert!(!kernel_space.page_table.translate(mid_rodata.floor()).unwrap().writable());
+    assert!(!kernel_space.page_table.translate(mid_data.floor()).unwrap().executable());
+    let last_page: VirtAddr = (memory_end() - PAGE_SIZE).into();
     assert_eq!(
-        kernel_space.page_table.translate(mid_text.floor()).unwrap().writable(),
-        false,
-    );
-    assert_eq!(
-        kernel_space.page_table.translate(mid_rodata.floor()).unwrap().writable(),
-        false,
-    );
-    assert_eq!(
-        kernel_space.page_table.translate(mid_data.floor()).unwrap().executable(),
-        false,
+        kernel_space.page_table.translate(last_page.floor()).unwrap().ppn().0,
+        last_page.floor().0,
     );
     println!("remap_test passed!");
 }
\ No newline at end of file
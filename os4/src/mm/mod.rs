@@ -3,20 +3,31 @@ mod address;
 mod page_table;
 mod frame_allocator;
 mod memory_set;
+mod user_ptr;
+mod swap;
+mod shm;
+mod asid;
+mod slab;
+mod aslr;
 
-pub use memory_set::{MemorySet, MapPermission, KERNEL_SPACE, remap_test};
-pub use heap_allocator::heap_test;
+pub use memory_set::{MemorySet, MapPermission, MmapFlags, FileMapping, MemoryUsage, VmaInfo, ENOMEM, AccessType, ElfError, KERNEL_SPACE, remap_test};
+pub use heap_allocator::{heap_test, heap_stats, HeapStats};
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum, VPNRange, StepByOne};
-pub use page_table::{PageTable, PageTableEntry, PTEFlags, translated_byte_buffer};
-pub use frame_allocator::{FrameTracker, frame_alloc };
+pub use page_table::{PageTable, PageTableEntry, PTEFlags, PageSize};
+pub use user_ptr::{UserPtr, UserSlice, BadAddress, copy_from_user, copy_to_user, copy_str_from_user};
+pub use slab::{slab_stats, SlabBox, SlabStats, TASK_CACHE};
+pub use shm::{shm_create, shm_frames, shm_collect};
+pub use frame_allocator::{FrameTracker, FrameStats, frame_alloc, frame_alloc_contiguous, frame_stats};
 
 
 
 pub fn init(){
     heap_allocator::init_heap();
-    // heap_allocator::heap_test();
     frame_allocator::init_frame_allocator();
-    // frame_allocator::frame_allocator_test();
+    heap_allocator::heap_test();
+    slab::slab_test();
+    frame_allocator::frame_allocator_test();
     KERNEL_SPACE.exclusive_access().activate();
+    asid::init_asid();
     // remap_test();
 }
\ No newline at end of file
@@ -1,9 +1,12 @@
 
 use bitflags::*;
-use alloc::vec;
-use alloc::vec::Vec;
-use super::{VirtPageNum, PhysPageNum, VirtAddr,FrameTracker, frame_alloc, StepByOne};
-use crate::task::current_task_note;
+use alloc::collections::BTreeMap;
+use super::{VirtPageNum, PhysPageNum, VirtAddr, PhysAddr, FrameTracker, frame_alloc};
+use super::asid::{Asid, asid_refresh};
+use crate::config::PAGING_LEVELS;
+
+/// satp MODE field: 8 for Sv39, 9 for Sv48, 10 for Sv57
+const SATP_MODE: usize = PAGING_LEVELS + 5;
 
 
 bitflags! {
@@ -54,111 +57,265 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    pub fn accessed(&self) -> bool {
+        (self.flags() & PTEFlags::A) != PTEFlags::empty()
+    }
+    pub fn dirty(&self) -> bool {
+        (self.flags() & PTEFlags::D) != PTEFlags::empty()
+    }
+    /// A valid entry with any of R/W/X maps memory instead of pointing to
+    /// the next level table.
+    pub fn is_leaf(&self) -> bool {
+        self.is_valid() && (self.flags() & (PTEFlags::R | PTEFlags::W | PTEFlags::X)) != PTEFlags::empty()
+    }
+}
+
+/// size of a leaf mapping, larger pages of Sv48/Sv57 are not used
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PageSize {
+    Size4K,
+    Size2M,
+    Size1G,
+}
+
+impl PageSize {
+    /// number of levels below the leaf
+    fn level(&self) -> usize {
+        match self {
+            PageSize::Size1G => 2,
+            PageSize::Size2M => 1,
+            PageSize::Size4K => 0,
+        }
+    }
+    /// index of the level in `VirtPageNum::indexes` holding the leaf
+    fn depth(&self) -> usize {
+        PAGING_LEVELS - 1 - self.level()
+    }
+    /// number of 4 KiB pages covered
+    pub fn pages(&self) -> usize {
+        1 << (9 * self.level())
+    }
+    fn from_depth(depth: usize) -> Self {
+        match PAGING_LEVELS - 1 - depth {
+            0 => PageSize::Size4K,
+            1 => PageSize::Size2M,
+            2 => PageSize::Size1G,
+            _ => panic!("leaf at depth {} is larger than 1GiB", depth),
+        }
+    }
 }
 
 pub struct PageTable {
     root_ppn: PhysPageNum,
-    frames: Vec<FrameTracker>,
+    /// frames of the root and intermediate tables, keyed by their ppn
+    frames: BTreeMap<PhysPageNum, FrameTracker>,
+    asid: Asid,
+    /// an intermediate table was freed since the last TLB flush, so
+    /// flushing the leaf entries is not enough
+    tables_freed: bool,
 }
 
 impl PageTable{
-    pub fn new()-> Self {
-        let frame = frame_alloc().unwrap();
-        PageTable {
-            root_ppn: frame.ppn,
-            frames: vec![frame],
-        }
+    /// `None` if there is no frame for the root table.
+    pub fn new()-> Option<Self> {
+        let frame = frame_alloc()?;
+        let mut frames = BTreeMap::new();
+        let root_ppn = frame.ppn;
+        frames.insert(root_ppn, frame);
+        Some(PageTable {
+            root_ppn,
+            frames,
+            asid: Asid::UNASSIGNED,
+            tables_freed: false,
+        })
     }
 
-    fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry>{
+    /// Find the entry for a page of `size` at `vpn`, creating the tables on
+    /// the way. `None` if a frame for a table could not be allocated.
+    fn find_pte_create(&mut self, vpn: VirtPageNum, size: PageSize) -> Option<&mut PageTableEntry>{
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
         let mut result: Option<&mut PageTableEntry> = None;
-        for i in 0..3 {
-            let pte = &mut ppn.get_pte_array()[idxs[i]];
-            if i == 2 {
+        for (i, idx) in idxs.iter().enumerate() {
+            let pte = &mut ppn.get_pte_array()[*idx];
+            if i == size.depth() {
                 result = Some(pte);
                 break;
             }
             if !pte.is_valid(){ 
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc()?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
-                self.frames.push(frame);
+                self.frames.insert(frame.ppn, frame);
             }
+            assert!(!pte.is_leaf(), "vpn {:?} is inside a huge page", vpn);
             ppn = pte.ppn();
         }
         result
     }
 
-    fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry>{
+    /// Find the entry mapping `vpn`, which is a leaf at any level or the
+    /// last level entry, together with the size of the page it maps.
+    fn find_pte(&self, vpn: VirtPageNum) -> Option<(&mut PageTableEntry, PageSize)>{
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
-        let mut result: Option<&mut PageTableEntry> = None;
-        for i in 0..3 {
-            let pte = &mut ppn.get_pte_array()[idxs[i]];
-            if i == 2 {
-                result = Some(pte);
-                break;
+        for (i, idx) in idxs.iter().enumerate() {
+            let pte = &mut ppn.get_pte_array()[*idx];
+            if i == PAGING_LEVELS - 1 || pte.is_leaf() {
+                return Some((pte, PageSize::from_depth(i)));
             }
             if !pte.is_valid(){ 
                 return None;
             }
             ppn = pte.ppn();
         }
-        result
+        None
     }
 
-    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags){
-        let pte = self.find_pte_create(vpn).unwrap();
+    /// Map a 4 KiB page, `None` if frames for the tables ran out.
+    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) -> Option<()> {
+        self.map_huge(vpn, ppn, flags, PageSize::Size4K)
+    }
+    /// Map a page of `size`, both `vpn` and `ppn` must be aligned to it.
+    /// `None` if frames for the tables ran out.
+    pub fn map_huge(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags, size: PageSize) -> Option<()> {
+        assert!(vpn.0 % size.pages() == 0 && ppn.0 % size.pages() == 0, "{:?} is not aligned to {:?}", vpn, size);
+        let pte = self.find_pte_create(vpn, size)?;
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        Some(())
     }
+    /// Unmap the page containing `vpn`, a huge page is removed as a whole.
+    ///
+    /// Intermediate tables left without any valid entry are freed on the way
+    /// back to the root.
     pub fn unmap(&mut self, vpn: VirtPageNum){
-        let pte = self.find_pte(vpn).unwrap();
+        let idxs = vpn.indexes();
+        // tables[i] is the table walked at level i
+        let mut tables = [self.root_ppn; PAGING_LEVELS];
+        let mut depth = 0;
+        loop {
+            let pte = &tables[depth].get_pte_array()[idxs[depth]];
+            if depth == PAGING_LEVELS - 1 || pte.is_leaf() {
+                break;
+            }
+            assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
+            tables[depth + 1] = pte.ppn();
+            depth += 1;
+        }
+        let pte = &mut tables[depth].get_pte_array()[idxs[depth]];
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
+        while depth > 0 {
+            if tables[depth].get_pte_array().iter().any(|pte| pte.is_valid()) {
+                break;
+            }
+            self.frames.remove(&tables[depth]);
+            self.tables_freed = true;
+            tables[depth - 1].get_pte_array()[idxs[depth - 1]] = PageTableEntry::empty();
+            depth -= 1;
+        }
+    }
+
+    /// Number of frames holding this page table, the root included.
+    pub fn table_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Rewrite the flags of a mapped page, keeping its frame and A/D bits.
+    pub fn set_flags(&mut self, vpn: VirtPageNum, flags: PTEFlags) {
+        let (pte, _) = self.find_pte(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before setting flags", vpn);
+        let kept = pte.flags() & (PTEFlags::A | PTEFlags::D);
+        *pte = PageTableEntry::new(pte.ppn(), flags | kept | PTEFlags::V);
+    }
+
+    /// Point a mapped page at `ppn` with `flags`, dropping the accessed and
+    /// dirty bits of the old frame.
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let (pte, _) = self.find_pte(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before remapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+    }
+
+    /// Clear the accessed bit of a mapped page, returning its old value.
+    pub fn clear_accessed(&mut self, vpn: VirtPageNum) -> bool {
+        let (pte, _) = self.find_pte(vpn).unwrap();
+        let accessed = pte.accessed();
+        *pte = PageTableEntry::new(pte.ppn(), pte.flags() - PTEFlags::A);
+        accessed
+    }
+
+    /// Clear the dirty bit of a mapped page, returning its old value.
+    pub fn clear_dirty(&mut self, vpn: VirtPageNum) -> bool {
+        let (pte, _) = self.find_pte(vpn).unwrap();
+        let dirty = pte.dirty();
+        *pte = PageTableEntry::new(pte.ppn(), pte.flags() - PTEFlags::D);
+        dirty
+    }
+
+    /// Set the accessed bit, and the dirty bit for a write, of a mapped page.
+    /// Used when the hardware leaves them to software, or when the kernel
+    /// writes the page through its own mapping.
+    pub fn mark_accessed(&mut self, vpn: VirtPageNum, write: bool) {
+        let (pte, _) = self.find_pte(vpn).unwrap();
+        let mut flags = pte.flags() | PTEFlags::A;
+        if write {
+            flags |= PTEFlags::D;
+        }
+        *pte = PageTableEntry::new(pte.ppn(), flags);
     }
 
     pub fn token(&self) -> usize {
-        8usize << 60 | self.root_ppn.0 
+        SATP_MODE << 60 | self.asid.bits() | self.root_ppn.0 
     }
 
     pub fn from_token(satp: usize) -> Self {
         Self {
             root_ppn: PhysPageNum::from(satp & ((1usize << 44)-1)),
-            frames: Vec::new(),
+            frames: BTreeMap::new(),
+            asid: Asid::UNASSIGNED,
+            tables_freed: false,
         }
     }
 
+    pub fn asid(&self) -> usize {
+        self.asid.id
+    }
+
+    /// Tag this page table as the kernel space.
+    pub fn set_kernel_asid(&mut self) {
+        self.asid = Asid::KERNEL;
+    }
+
+    /// Get an ASID of the current generation before activating the table.
+    pub fn refresh_asid(&mut self) {
+        asid_refresh(&mut self.asid);
+    }
+
+    /// Whether an intermediate table was freed since the last call.
+    pub fn take_tables_freed(&mut self) -> bool {
+        core::mem::replace(&mut self.tables_freed, false)
+    }
+
+    /// Entry mapping `vpn`. Inside a huge page the entry is adjusted to the
+    /// 4 KiB frame backing `vpn`.
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry>{
         self.find_pte(vpn)
-            .map(|pte| {pte.clone()})
+            .map(|(pte, size)| {
+                if size == PageSize::Size4K {
+                    *pte
+                } else {
+                    let ppn = PhysPageNum(pte.ppn().0 + (vpn.0 & (size.pages() - 1)));
+                    PageTableEntry::new(ppn, pte.flags())
+                }
+            })
     }
 
-}
-
+    pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
+        self.translate(va.floor()).map(|pte| {
+            let aligned_pa: PhysAddr = pte.ppn().into();
+            PhysAddr(aligned_pa.0 + va.page_offset())
+        })
+    }
 
-pub fn translated_byte_buffer(
-    token: usize,
-    ptr: *const u8,
-    len: usize
-) -> Vec<& 'static [u8]> {
-    let page_table = PageTable::from_token(token);
-    let mut start = ptr as usize;
-    let end = start + len;
-    let mut v = Vec::new();
-    while start < end {
-        let start_va = VirtAddr::from(start);
-        let mut vpn = start_va.floor();
-        let ppn = page_table
-            .translate(vpn)
-            .unwrap()
-            .ppn();
-        vpn.step();
-        let mut end_va: VirtAddr = vpn.into();
-        end_va = end_va.min(VirtAddr::from(end));
-        v.push(&ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
-        start = end_va.into();
-    }
-    v
-}
\ No newline at end of file
+}
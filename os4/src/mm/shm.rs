@@ -0,0 +1,73 @@
+//! Shared memory segments
+//!
+//! A segment is a list of frames registered under a key. Every task attaching
+//! it maps the same frames, so they are reference counted and freed once the
+//! registry and the last mapping drop them.
+
+use super::{frame_alloc, FrameTracker};
+use crate::config::PAGE_SIZE;
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+struct ShmSegment {
+    frames: Vec<Arc<FrameTracker>>,
+    /// whether any task has mapped the segment yet
+    attached: bool,
+}
+
+impl ShmSegment {
+    /// Whether some address space still maps the segment.
+    fn mapped(&self) -> bool {
+        self.frames.iter().any(|frame| Arc::strong_count(frame) > 1)
+    }
+}
+
+lazy_static! {
+    static ref SHM_SEGMENTS: UPSafeCell<BTreeMap<usize, ShmSegment>> = unsafe {
+        UPSafeCell::new(BTreeMap::new())
+    };
+}
+
+/// Create a segment of `len` bytes under `key`, or find the existing one,
+/// which must then have the same number of pages.
+pub fn shm_create(key: usize, len: usize) -> isize {
+    let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+    if pages == 0 {
+        return -1;
+    }
+    let mut segments = SHM_SEGMENTS.exclusive_access();
+    if let Some(segment) = segments.get(&key) {
+        return if segment.frames.len() == pages { 0 } else { -1 };
+    }
+    let mut frames = Vec::with_capacity(pages);
+    for _ in 0..pages {
+        match frame_alloc() {
+            Some(frame) => frames.push(Arc::new(frame)),
+            None => return -1,
+        }
+    }
+    segments.insert(key, ShmSegment { frames, attached: false });
+    0
+}
+
+/// Frames of the segment under `key`.
+pub fn shm_frames(key: usize) -> Option<Vec<Arc<FrameTracker>>> {
+    SHM_SEGMENTS
+        .exclusive_access()
+        .get(&key)
+        .map(|segment| segment.frames.clone())
+}
+
+/// Update the registry after mappings changed: a segment which has been
+/// attached and is no longer mapped anywhere is removed, freeing its frames.
+pub fn shm_collect() {
+    SHM_SEGMENTS.exclusive_access().retain(|_, segment| {
+        if segment.mapped() {
+            segment.attached = true;
+        }
+        segment.mapped() || !segment.attached
+    });
+}
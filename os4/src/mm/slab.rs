@@ -0,0 +1,315 @@
+//! Slab allocator for small kernel objects
+//!
+//! Each cache hands out objects of one size, carved from slabs of frames
+//! taken from `FRAME_ALLOCATOR`. A slab is aligned to its own size and starts
+//! with a [`SlabHeader`], so the slab of an object is found by masking its
+//! address. Objects of the same size share slabs, which keeps the buddy heap
+//! from fragmenting under churn.
+//!
+//! Well known kernel objects have caches of their own so that their memory
+//! shows up by name in [`slab_stats`]: tasks and areas are boxed in theirs
+//! with [`SlabBox`], and easy-fs block buffers, the only allocations aligned
+//! to `BLOCK_SZ`, are routed to theirs by the global allocator. Everything
+//! else up to 2 KiB goes to the `kmalloc-*` size classes.
+
+use super::frame_allocator::{frame_alloc_pinned, frame_dealloc_pinned};
+use super::memory_set::MapArea;
+use super::PhysAddr;
+use crate::config::PAGE_SIZE;
+use crate::task::{reclaim_frames, TaskControlBlock};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::alloc::{AllocError, Allocator, Layout};
+use core::mem::size_of;
+use core::ptr::{slice_from_raw_parts_mut, NonNull};
+use easy_fs::BLOCK_SZ;
+use spin::Mutex;
+
+/// a slab holds at least this many objects
+const SLAB_MIN_OBJECTS: usize = 8;
+
+/// placed at the start of every slab
+struct SlabHeader {
+    /// neighbours in the list of partial slabs, 0 for none
+    prev: usize,
+    next: usize,
+    /// first free object, 0 for none, each free object holds the next one
+    free: usize,
+    in_use: usize,
+}
+
+struct SlabCacheInner {
+    /// slabs with free objects
+    partial: usize,
+    slabs: usize,
+    in_use: usize,
+}
+
+pub struct SlabCache {
+    name: &'static str,
+    obj_size: usize,
+    inner: Mutex<SlabCacheInner>,
+}
+
+/// usage of a slab cache
+#[derive(Copy, Clone, Debug)]
+pub struct SlabStats {
+    pub name: &'static str,
+    pub obj_size: usize,
+    pub slabs: usize,
+    /// objects allocated
+    pub in_use: usize,
+    /// objects the slabs can hold
+    pub capacity: usize,
+}
+
+impl SlabCache {
+    const fn new(name: &'static str, obj_size: usize) -> Self {
+        Self {
+            name,
+            // room for the free list link, and keep objects word aligned
+            obj_size: (obj_size + 7) / 8 * 8,
+            inner: Mutex::new(SlabCacheInner {
+                partial: 0,
+                slabs: 0,
+                in_use: 0,
+            }),
+        }
+    }
+
+    /// Objects are aligned to the largest power of two dividing their size.
+    fn align(&self) -> usize {
+        (self.obj_size & self.obj_size.wrapping_neg()).min(PAGE_SIZE)
+    }
+
+    fn first_offset(&self) -> usize {
+        let align = self.align();
+        (size_of::<SlabHeader>() + align - 1) / align * align
+    }
+
+    fn slab_pages(&self) -> usize {
+        let bytes = self.first_offset() + SLAB_MIN_OBJECTS * self.obj_size;
+        ((bytes + PAGE_SIZE - 1) / PAGE_SIZE).next_power_of_two()
+    }
+
+    fn slab_bytes(&self) -> usize {
+        self.slab_pages() * PAGE_SIZE
+    }
+
+    fn capacity(&self) -> usize {
+        (self.slab_bytes() - self.first_offset()) / self.obj_size
+    }
+
+    fn header(slab: usize) -> &'static mut SlabHeader {
+        unsafe { &mut *(slab as *mut SlabHeader) }
+    }
+
+    /// Get a new slab from the frame allocator and thread its objects.
+    fn grow(&self, inner: &mut SlabCacheInner) -> bool {
+        let pages = self.slab_pages();
+        let slab: PhysAddr = match frame_alloc_pinned(pages, pages) {
+            Some(ppn) => ppn.into(),
+            None => return false,
+        };
+        let first = slab.0 + self.first_offset();
+        let capacity = self.capacity();
+        for i in 0..capacity {
+            let obj = first + i * self.obj_size;
+            let next = if i + 1 == capacity { 0 } else { obj + self.obj_size };
+            unsafe {
+                *(obj as *mut usize) = next;
+            }
+        }
+        *Self::header(slab.0) = SlabHeader {
+            prev: 0,
+            next: 0,
+            free: first,
+            in_use: 0,
+        };
+        self.push(inner, slab.0);
+        inner.slabs += 1;
+        true
+    }
+
+    fn push(&self, inner: &mut SlabCacheInner, slab: usize) {
+        let header = Self::header(slab);
+        header.prev = 0;
+        header.next = inner.partial;
+        if inner.partial != 0 {
+            Self::header(inner.partial).prev = slab;
+        }
+        inner.partial = slab;
+    }
+
+    fn remove(&self, inner: &mut SlabCacheInner, slab: usize) {
+        let header = Self::header(slab);
+        if header.prev != 0 {
+            Self::header(header.prev).next = header.next;
+        } else {
+            inner.partial = header.next;
+        }
+        if header.next != 0 {
+            Self::header(header.next).prev = header.prev;
+        }
+    }
+
+    fn alloc(&self) -> *mut u8 {
+        let mut inner = self.inner.lock();
+        if inner.partial == 0 && !self.grow(&mut inner) {
+            return core::ptr::null_mut();
+        }
+        let slab = inner.partial;
+        let header = Self::header(slab);
+        let obj = header.free;
+        header.free = unsafe { *(obj as *const usize) };
+        header.in_use += 1;
+        if header.free == 0 {
+            self.remove(&mut inner, slab);
+        }
+        inner.in_use += 1;
+        obj as *mut u8
+    }
+
+    fn dealloc(&self, ptr: *mut u8) {
+        let mut inner = self.inner.lock();
+        let obj = ptr as usize;
+        let slab = obj & !(self.slab_bytes() - 1);
+        let header = Self::header(slab);
+        if header.free == 0 {
+            // the slab was full, so it is not on the partial list
+            self.push(&mut inner, slab);
+        }
+        unsafe {
+            *(obj as *mut usize) = header.free;
+        }
+        header.free = obj;
+        header.in_use -= 1;
+        inner.in_use -= 1;
+        // give an empty slab back unless it is the last one
+        if header.in_use == 0 && inner.slabs > 1 {
+            self.remove(&mut inner, slab);
+            inner.slabs -= 1;
+            frame_dealloc_pinned(PhysAddr::from(slab).into(), self.slab_pages());
+        }
+    }
+
+    fn stats(&self) -> SlabStats {
+        let inner = self.inner.lock();
+        SlabStats {
+            name: self.name,
+            obj_size: self.obj_size,
+            slabs: inner.slabs,
+            in_use: inner.in_use,
+            capacity: inner.slabs * self.capacity(),
+        }
+    }
+}
+
+/// Objects are allocated with `Box::new_in`, which fails on objects the
+/// cache is too small for.
+unsafe impl Allocator for SlabCache {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() > self.obj_size || layout.align() > self.align() {
+            return Err(AllocError);
+        }
+        loop {
+            if let Some(obj) = NonNull::new(self.alloc()) {
+                return NonNull::new(slice_from_raw_parts_mut(obj.as_ptr(), self.obj_size))
+                    .ok_or(AllocError);
+            }
+            // swapping out pages allocates, so not with the cache locked
+            if !reclaim_frames() {
+                return Err(AllocError);
+            }
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
+        self.dealloc(ptr.as_ptr());
+    }
+}
+
+/// an object boxed in a slab cache of its own
+pub type SlabBox<T> = Box<T, &'static SlabCache>;
+
+pub static TASK_CACHE: SlabCache = SlabCache::new("task", size_of::<TaskControlBlock>());
+pub static MAP_AREA_CACHE: SlabCache = SlabCache::new("map_area", size_of::<MapArea>());
+static BLOCK_CACHE: SlabCache = SlabCache::new("block", BLOCK_SZ);
+
+static SIZE_CACHES: [SlabCache; 8] = [
+    SlabCache::new("kmalloc-16", 16),
+    SlabCache::new("kmalloc-32", 32),
+    SlabCache::new("kmalloc-64", 64),
+    SlabCache::new("kmalloc-128", 128),
+    SlabCache::new("kmalloc-256", 256),
+    SlabCache::new("kmalloc-512", 512),
+    SlabCache::new("kmalloc-1024", 1024),
+    SlabCache::new("kmalloc-2048", 2048),
+];
+
+/// The cache serving `layout`, `None` if it should come from the heap.
+fn cache_for(layout: &Layout) -> Option<&'static SlabCache> {
+    if layout.size() == BLOCK_SZ && layout.align() == BLOCK_SZ {
+        return Some(&BLOCK_CACHE);
+    }
+    let size = (layout.size() + 7) / 8 * 8;
+    SIZE_CACHES
+        .iter()
+        .find(|cache| cache.obj_size >= size.max(layout.align()))
+}
+
+/// Allocate from a slab cache, `None` if `layout` is not served by one or
+/// no slab could be had, so the caller falls back to the heap.
+pub fn slab_alloc(layout: &Layout) -> Option<*mut u8> {
+    cache_for(layout)
+        .map(|cache| cache.alloc())
+        .filter(|ptr| !ptr.is_null())
+}
+
+/// Free `ptr` if `layout` is served by a slab cache.
+pub fn slab_dealloc(ptr: *mut u8, layout: &Layout) -> bool {
+    match cache_for(layout) {
+        Some(cache) => {
+            cache.dealloc(ptr);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn slab_stats() -> Vec<SlabStats> {
+    [&TASK_CACHE, &MAP_AREA_CACHE, &BLOCK_CACHE]
+        .iter()
+        .copied()
+        .chain(SIZE_CACHES.iter())
+        .map(|cache| cache.stats())
+        .collect()
+}
+
+pub fn slab_test() {
+    let before = slab_stats();
+    let v: Vec<Box<[u8; 100]>> = (0..64).map(|_| Box::new([0u8; 100])).collect();
+    for b in v.iter() {
+        let addr = b.as_ptr() as usize;
+        assert_eq!(addr % 8, 0);
+    }
+    let during = slab_stats();
+    let idx = before.iter().position(|s| s.name == "kmalloc-128").unwrap();
+    assert!(during[idx].in_use >= before[idx].in_use + 64);
+    drop(v);
+    let after = slab_stats();
+    assert_eq!(after[idx].in_use, before[idx].in_use);
+    // a block buffer goes to its own cache, not to kmalloc-512
+    #[repr(align(512))]
+    struct Block([u8; BLOCK_SZ]);
+    let block = Box::new(Block([0; BLOCK_SZ]));
+    let during = slab_stats();
+    let blocks = before.iter().position(|s| s.name == "block").unwrap();
+    let kmalloc = before.iter().position(|s| s.name == "kmalloc-512").unwrap();
+    assert_eq!(during[blocks].in_use, before[blocks].in_use + 1);
+    assert_eq!(during[kmalloc].in_use, before[kmalloc].in_use);
+    assert_eq!(block.0.as_ptr() as usize % BLOCK_SZ, 0);
+    drop(block);
+    assert_eq!(slab_stats()[blocks].in_use, before[blocks].in_use);
+    println!("slab_test passed!");
+}
@@ -0,0 +1,93 @@
+//! Swap area on a block device
+//!
+//! The device is cut into page sized slots of `PAGE_SIZE / BLOCK_SZ` blocks.
+//! A [`SwapSlot`] owns one of them the way a `FrameTracker` owns a frame.
+//! Without a swap device there are no slots, so pages are never swapped out.
+
+use super::PhysPageNum;
+use crate::config::{PAGE_SIZE, SWAP_PAGES};
+use crate::drivers::SWAP_DEVICE;
+use crate::sync::UPSafeCell;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use easy_fs::{BlockDevice, BLOCK_SZ};
+use lazy_static::*;
+
+const BLOCKS_PER_PAGE: usize = PAGE_SIZE / BLOCK_SZ;
+
+pub struct SwapSlot {
+    pub id: usize,
+}
+
+/// The swap device, which exists once a slot has been handed out.
+fn swap_device() -> &'static Arc<dyn BlockDevice> {
+    SWAP_DEVICE.as_ref().unwrap()
+}
+
+impl SwapSlot {
+    /// Save the content of frame `ppn` into this slot.
+    pub fn write(&self, ppn: PhysPageNum) {
+        let bytes = ppn.get_bytes_array();
+        for (i, block) in bytes.chunks(BLOCK_SZ).enumerate() {
+            swap_device().write_block(self.id * BLOCKS_PER_PAGE + i, block);
+        }
+    }
+
+    /// Load the content of this slot into frame `ppn`.
+    pub fn read(&self, ppn: PhysPageNum) {
+        let bytes = ppn.get_bytes_array();
+        for (i, block) in bytes.chunks_mut(BLOCK_SZ).enumerate() {
+            swap_device().read_block(self.id * BLOCKS_PER_PAGE + i, block);
+        }
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        SWAP_ALLOCATOR.exclusive_access().dealloc(self.id);
+    }
+}
+
+struct SwapAllocator {
+    current: usize,
+    end: usize,
+    recycled: Vec<usize>,
+}
+
+impl SwapAllocator {
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(id) = self.recycled.pop() {
+            Some(id)
+        } else if self.current == self.end {
+            None
+        } else {
+            self.current += 1;
+            Some(self.current - 1)
+        }
+    }
+
+    fn dealloc(&mut self, id: usize) {
+        if id >= self.current || self.recycled.contains(&id) {
+            panic!("Swap slot {} has not been allocated!", id);
+        }
+        self.recycled.push(id);
+    }
+}
+
+lazy_static! {
+    static ref SWAP_ALLOCATOR: UPSafeCell<SwapAllocator> = unsafe {
+        UPSafeCell::new(SwapAllocator {
+            current: 0,
+            end: if SWAP_DEVICE.is_some() { SWAP_PAGES } else { 0 },
+            recycled: Vec::new(),
+        })
+    };
+}
+
+/// Allocate a swap slot, `None` if the swap area is full or missing.
+pub fn swap_alloc() -> Option<SwapSlot> {
+    SWAP_ALLOCATOR
+        .exclusive_access()
+        .alloc()
+        .map(|id| SwapSlot { id })
+}
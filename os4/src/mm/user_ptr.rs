@@ -0,0 +1,135 @@
+//! Checked access to user memory from the kernel
+//!
+//! Every page touched by a copy is translated through the current task's
+//! [`MemorySet`], so lazily backed pages are faulted in and the area's
+//! permission is checked for each of them. A bad pointer yields
+//! [`BadAddress`] instead of a kernel panic.
+
+use super::{AccessType, MemorySet, VirtAddr};
+use crate::config::PAGE_SIZE;
+use crate::task::with_current_memory_set;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::size_of;
+
+/// The kernel failed to access a user pointer, reported like `EFAULT`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BadAddress;
+
+/// Call `f` with the physical bytes of every page-sized piece of
+/// `[va, va + len)` and the offset of that piece from `va`.
+fn for_each_piece(
+    memory_set: &mut MemorySet,
+    va: usize,
+    len: usize,
+    access: AccessType,
+    mut f: impl FnMut(&'static mut [u8], usize),
+) -> Result<(), BadAddress> {
+    if !VirtAddr::is_user_range(va, len) {
+        return Err(BadAddress);
+    }
+    let end = va + len;
+    let mut start = va;
+    while start < end {
+        let start_va = VirtAddr::from(start);
+        let piece_end = ((start / PAGE_SIZE + 1) * PAGE_SIZE).min(end);
+        let pa = memory_set.translate_user(start_va, access).ok_or(BadAddress)?;
+        let bytes = unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut u8, piece_end - start) };
+        f(bytes, start - va);
+        start = piece_end;
+    }
+    Ok(())
+}
+
+/// Copy `dst.len()` bytes from user address `src` into `dst`.
+pub fn copy_from_user(memory_set: &mut MemorySet, dst: &mut [u8], src: usize) -> Result<(), BadAddress> {
+    for_each_piece(memory_set, src, dst.len(), AccessType::Read, |piece, offset| {
+        dst[offset..offset + piece.len()].copy_from_slice(piece);
+    })
+}
+
+/// Copy `src` to user address `dst`.
+pub fn copy_to_user(memory_set: &mut MemorySet, dst: usize, src: &[u8]) -> Result<(), BadAddress> {
+    for_each_piece(memory_set, dst, src.len(), AccessType::Write, |piece, offset| {
+        piece.copy_from_slice(&src[offset..offset + piece.len()]);
+    })
+}
+
+/// Read the NUL terminated string at user address `src`, at most `max_len`
+/// bytes long without the NUL.
+pub fn copy_str_from_user(src: *const u8, max_len: usize) -> Result<String, BadAddress> {
+    let mut bytes = Vec::new();
+    let mut addr = src as usize;
+    loop {
+        // read up to the end of the page, the next one may not be mapped
+        let piece = PAGE_SIZE - addr % PAGE_SIZE;
+        let mut buf = vec![0u8; piece];
+        with_current_memory_set(|memory_set| copy_from_user(memory_set, &mut buf, addr))?;
+        if let Some(nul) = buf.iter().position(|b| *b == 0) {
+            bytes.extend_from_slice(&buf[..nul]);
+            break;
+        }
+        bytes.extend_from_slice(&buf);
+        if bytes.len() > max_len {
+            return Err(BadAddress);
+        }
+        addr += piece;
+    }
+    if bytes.len() > max_len {
+        return Err(BadAddress);
+    }
+    String::from_utf8(bytes).map_err(|_| BadAddress)
+}
+
+/// A pointer to a `T` in the current task's address space.
+pub struct UserPtr<T> {
+    addr: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> UserPtr<T> {
+    pub fn new(ptr: *const T) -> Self {
+        Self {
+            addr: ptr as usize,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn write(&self, val: &T) -> Result<(), BadAddress> {
+        let bytes =
+            unsafe { core::slice::from_raw_parts(val as *const T as *const u8, size_of::<T>()) };
+        with_current_memory_set(|memory_set| copy_to_user(memory_set, self.addr, bytes))
+    }
+}
+
+/// A byte buffer in the current task's address space.
+pub struct UserSlice {
+    addr: usize,
+    len: usize,
+}
+
+impl UserSlice {
+    pub fn new(ptr: *const u8, len: usize) -> Self {
+        Self {
+            addr: ptr as usize,
+            len,
+        }
+    }
+
+    /// Copy the whole buffer into kernel space.
+    pub fn read(&self) -> Result<Vec<u8>, BadAddress> {
+        let mut buf = vec![0u8; self.len];
+        with_current_memory_set(|memory_set| copy_from_user(memory_set, &mut buf, self.addr))?;
+        Ok(buf)
+    }
+
+    /// Fill the head of the buffer with `data`.
+    pub fn write(&self, data: &[u8]) -> Result<(), BadAddress> {
+        if data.len() > self.len {
+            return Err(BadAddress);
+        }
+        with_current_memory_set(|memory_set| copy_to_user(memory_set, self.addr, data))
+    }
+}
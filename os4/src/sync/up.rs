@@ -28,4 +28,9 @@ impl<T> UPSafeCell<T> {
     pub fn exclusive_access(&self) -> RefMut<'_, T> {
         self.inner.borrow_mut()
     }
+    /// `None` instead of a panic if the data has been borrowed, for use in
+    /// the panic handler and by allocators that may be called from anywhere.
+    pub fn try_access(&self) -> Option<RefMut<'_, T>> {
+        self.inner.try_borrow_mut().ok()
+    }
 }
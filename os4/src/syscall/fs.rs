@@ -1,20 +1,77 @@
 //! File and filesystem-related syscalls
 
-const FD_STDOUT: usize = 1;
-use crate::mm::translated_byte_buffer;
-use crate::task::current_user_token;
+use crate::config::PAGE_SIZE;
+use crate::fs::{open_file, OpenFlags};
+use crate::mm::{copy_str_from_user, UserSlice};
+use crate::task::{current_add_file, current_close_file, current_file};
+use alloc::vec;
+
+/// longest file name easy-fs can hold
+const NAME_LENGTH_LIMIT: usize = 27;
 
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
-    match fd {
-        FD_STDOUT => {
-            let buffers = translated_byte_buffer(current_user_token(), buf, len);
-            for buffer in buffers {
-                print!("{}", core::str::from_utf8(buffer).unwrap());
-            }
-            len as isize
-        },
-        _ => {
-            panic!("Unsupported fd in sys_write!");
+    let file = match current_file(fd) {
+        Some(file) if file.writable() => file,
+        _ => return -1,
+    };
+    let mut written = 0;
+    // copy a page at a time, so a large buffer does not fill the kernel heap
+    while written < len {
+        let piece = PAGE_SIZE.min(len - written);
+        let data = match UserSlice::new(buf.wrapping_add(written), piece).read() {
+            Ok(data) => data,
+            Err(_) => return -1,
+        };
+        let size = file.write(&data);
+        written += size;
+        if size < piece {
+            break;
+        }
+    }
+    written as isize
+}
+
+pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
+    let file = match current_file(fd) {
+        Some(file) if file.readable() => file,
+        _ => return -1,
+    };
+    let mut read = 0;
+    while read < len {
+        let mut data = vec![0u8; PAGE_SIZE.min(len - read)];
+        let size = file.read(&mut data);
+        if UserSlice::new(buf.wrapping_add(read), size).write(&data[..size]).is_err() {
+            return -1;
+        }
+        read += size;
+        if size < data.len() {
+            break;
         }
     }
+    read as isize
+}
+
+/// Open the file `path` of the root directory, `_dirfd` and `_mode` are
+/// ignored.
+pub fn sys_openat(_dirfd: usize, path: *const u8, flags: u32, _mode: u32) -> isize {
+    let path = match copy_str_from_user(path, NAME_LENGTH_LIMIT) {
+        Ok(path) => path,
+        Err(_) => return -1,
+    };
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return -1,
+    };
+    match open_file(path.as_str(), flags) {
+        Some(inode) => current_add_file(inode) as isize,
+        None => -1,
+    }
+}
+
+pub fn sys_close(fd: usize) -> isize {
+    if current_close_file(fd) {
+        0
+    } else {
+        -1
+    }
 }
@@ -1,10 +1,17 @@
 //! Process management syscalls
 
-use crate::config::{MAX_SYSCALL_NUM};
-use crate::task::{exit_current_and_run_next, suspend_current_and_run_next, TaskStatus, current_user_token, TaskInfo, get_task_info, current_mmap, current_munmap};
-use crate::mm::{MapPermission, PageTable, VirtAddr, PhysAddr};
+use crate::config::{MAX_PRIORITY, PAGE_SIZE};
+use crate::task::{exit_current_and_run_next, sleep_current_and_run_next, current_pid, fork_current, spawn_current, exec_current, waitpid_current, suspend_current_and_run_next, TaskInfo, get_task_info, set_current_priority, get_memory_usage, get_current_vmas, current_mmap, current_munmap, current_brk, current_msync, current_file, current_mprotect, current_shm_attach, current_shm_detach};
+use crate::mm::{MapPermission, MmapFlags, FileMapping, MemoryUsage, VmaInfo, VirtAddr, UserPtr, copy_str_from_user, shm_create};
+use crate::loader::get_app_data_by_name;
 use crate::timer::get_time_us;
 
+/// longest app name exec and spawn accept
+const APP_NAME_LIMIT: usize = 64;
+
+/// MS_ASYNC, MS_INVALIDATE and MS_SYNC
+const MS_FLAGS: usize = 0x7;
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct TimeVal {
@@ -17,7 +24,7 @@ pub struct TimeVal {
 /// task exits and submit an exit code
 pub fn sys_exit(exit_code: i32) -> ! {
     info!("[kernel] Application exited with code {}", exit_code);
-    exit_current_and_run_next();
+    exit_current_and_run_next(exit_code);
     panic!("Unreachable in sys_exit!");
 }
 
@@ -26,65 +33,216 @@ pub fn sys_yield() -> isize {
     suspend_current_and_run_next();
     0
 }
-pub fn get_pa(va: usize) -> usize{ 
-    let current_token = current_user_token();
-    let pt: PageTable = PageTable::from_token(current_token);
-    let va: VirtAddr = VirtAddr::from(va);  
-    let ppn = pt.translate(va.floor()).unwrap().ppn();
-    let pa: PhysAddr = ppn.into();
-    let pa: usize = pa.into();
-    pa + va.page_offset()
+
+pub fn sys_getpid() -> isize {
+    current_pid() as isize
+}
+
+/// Copy the current task, the child gets 0 and the parent the child's pid.
+pub fn sys_fork() -> isize {
+    fork_current()
+}
+
+/// Run the app called `path` in place of the current one, `_args` are
+/// ignored.
+pub fn sys_exec(path: *const u8, _args: *const usize) -> isize {
+    let name = match copy_str_from_user(path, APP_NAME_LIMIT) {
+        Ok(name) => name,
+        Err(_) => return -1,
+    };
+    match get_app_data_by_name(name.as_str()) {
+        Some(data) => exec_current(data),
+        None => -1,
+    }
+}
+
+/// Start the app called `path` as a child, returning its pid.
+pub fn sys_spawn(path: *const u8) -> isize {
+    let name = match copy_str_from_user(path, APP_NAME_LIMIT) {
+        Ok(name) => name,
+        Err(_) => return -1,
+    };
+    match get_app_data_by_name(name.as_str()) {
+        Some(data) => spawn_current(data),
+        None => -1,
+    }
+}
+
+/// Reap the exited child `pid`, or any child for -1, storing its exit code
+/// at `exit_code_ptr`. Returns -1 if there is no such child and -2 if it has
+/// not exited yet.
+pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+    let (found, exit_code) = waitpid_current(pid);
+    if found < 0 {
+        return found;
+    }
+    match UserPtr::new(exit_code_ptr).write(&exit_code) {
+        Ok(()) => found,
+        Err(_) => -1,
+    }
 }
 
+/// Block for `ms` milliseconds, 0 only yields.
+pub fn sys_sleep(ms: usize) -> isize {
+    if ms == 0 {
+        suspend_current_and_run_next();
+    } else {
+        sleep_current_and_run_next(ms);
+    }
+    0
+}
 
 // YOUR JOB: 引入虚地址后重写 sys_get_time
 pub fn sys_get_time(_ts: *mut TimeVal, _tz: usize) -> isize {
     let _us = get_time_us();
-    let pa = get_pa(_ts as usize);
-    unsafe{
-        let time = pa as *mut TimeVal;
-        * time = TimeVal {
-            sec: _us / 1_000_000,
-            usec: _us % 1_000_000,
-        }
+    let time = TimeVal {
+        sec: _us / 1_000_000,
+        usec: _us % 1_000_000,
+    };
+    match UserPtr::new(_ts).write(&time) {
+        Ok(()) => 0,
+        Err(_) => -1,
     }
-
-    0
 }
 
 // CLUE: 从 ch4 开始不再对调度算法进行测试~
-pub fn sys_set_priority(_prio: isize) -> isize {
-    -1
+/// Set the priority of the current task, at least 2, and return it.
+/// Priorities above `MAX_PRIORITY` are accepted but scheduled as
+/// `MAX_PRIORITY`.
+pub fn sys_set_priority(prio: isize) -> isize {
+    if prio < 2 {
+        return -1;
+    }
+    set_current_priority((prio as usize).min(MAX_PRIORITY));
+    prio
 }
 
 // YOUR JOB: 扩展内核以实现 sys_mmap 和 sys_munmap
-pub fn sys_mmap(_start: usize, _len: usize, _port: usize) -> isize {
-    let va = VirtAddr(_start);
-    if !va.is_align() {
+/// Map anonymous memory, or the file `fd` from `offset`, and return its
+/// address. `start` is a hint, or the exact address with
+/// `MAP_FIXED`/`MAP_FIXED_NOREPLACE`; a NULL hint lets the kernel choose.
+pub fn sys_mmap(start: usize, len: usize, port: usize, flags: usize, fd: usize, offset: usize) -> isize {
+    let flags = match MmapFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return -1,
+    };
+    // exactly one of shared and private
+    if flags.contains(MmapFlags::SHARED) == flags.contains(MmapFlags::PRIVATE) {
+        return -1
+    }
+    let file = if flags.contains(MmapFlags::ANONYMOUS) {
+        None
+    } else {
+        let file = match current_file(fd) {
+            Some(file) if file.readable() && offset % PAGE_SIZE == 0 => file,
+            _ => return -1,
+        };
+        // writes to a shared mapping reach the file
+        if flags.contains(MmapFlags::SHARED) && (port & 0x2) != 0 && !file.writable() {
+            return -1
+        }
+        match file.page_cache() {
+            Some(cache) => Some(FileMapping::new(cache, offset, file.writable())),
+            None => return -1,
+        }
+    };
+    // a hint outside the user half is just not taken
+    let fixed = flags.intersects(MmapFlags::FIXED | MmapFlags::FIXED_NOREPLACE);
+    if !VirtAddr::is_user_range(if fixed { start } else { 0 }, len) {
         return -1
     }
-    if ((_port & 0x7) == 0) || _port > 7 {
+    if ((port & 0x7) == 0) || port > 7 {
         return -1
     }
-    let perm = MapPermission::from_bits(((_port<<1) + 16) as u8).unwrap();
-    current_mmap(va, _len, perm)
+    let perm = MapPermission::from_bits(((port<<1) + 16) as u8).unwrap();
+    current_mmap(VirtAddr(start), len, perm, flags, file)
+}
+
+/// Write the modified pages of shared file mappings in the range back to
+/// their files. Every write is synchronous, so `flags` only gets checked.
+pub fn sys_msync(start: usize, len: usize, flags: usize) -> isize {
+    let va = VirtAddr(start);
+    if !va.is_align() || !VirtAddr::is_user_range(start, len) || flags & !MS_FLAGS != 0 {
+        return -1
+    }
+    current_msync(va, len)
+}
+
+/// Set the program break to `addr` like Linux brk: the new break is
+/// returned, or the old one if it could not move, and 0 just queries it.
+pub fn sys_brk(addr: usize) -> isize {
+    current_brk(addr) as isize
 }
 
 pub fn sys_munmap(_start: usize, _len: usize) -> isize {
     let va = VirtAddr(_start);
-    if !va.is_align() {
+    if !va.is_align() || !VirtAddr::is_user_range(_start, _len) {
         return -1
     }
     current_munmap(va, _len)
 
 }
 
+/// Change the permission of the pages in the range to `port`, as in mmap.
+/// PROT_NONE is not supported: a `port` without R, W or X would leave
+/// valid but unusable PTEs behind, so it is refused like in sys_mmap.
+pub fn sys_mprotect(start: usize, len: usize, port: usize) -> isize {
+    let va = VirtAddr(start);
+    if !va.is_align() || !VirtAddr::is_user_range(start, len) || (port & 0x7) == 0 || port > 7 {
+        return -1
+    }
+    let perm = MapPermission::from_bits(((port << 1) + 16) as u8).unwrap();
+    current_mprotect(va, len, perm)
+}
+
+/// Create the shared memory segment `key` of `len` bytes, or check the
+/// existing one has that size.
+pub fn sys_shmget(key: usize, len: usize) -> isize {
+    shm_create(key, len)
+}
+
+/// Map the shared memory segment `key` at `start` with `port` like mmap.
+pub fn sys_shmat(key: usize, start: usize, port: usize) -> isize {
+    let va = VirtAddr(start);
+    if !va.is_align() || (port & 0x7) == 0 || port > 7 {
+        return -1
+    }
+    let perm = MapPermission::from_bits(((port << 1) + 16) as u8).unwrap();
+    current_shm_attach(key, va, perm)
+}
+
+pub fn sys_shmdt(start: usize) -> isize {
+    let va = VirtAddr(start);
+    if !va.is_align() {
+        return -1
+    }
+    current_shm_detach(va)
+}
+
 // YOUR JOB: 引入虚地址后重写 sys_task_info
 pub fn sys_task_info(ti: *mut TaskInfo) -> isize {
-    let pa = get_pa(ti as usize);
-    if get_task_info(pa as *mut TaskInfo) == 0 {
-        return 0 
+    match UserPtr::new(ti).write(&get_task_info()) {
+        Ok(()) => 0,
+        Err(_) => -1,  //  失败
+    }
+}
+
+/// Report the resident pages of the current task and its page limit.
+pub fn sys_task_memory(usage: *mut MemoryUsage) -> isize {
+    match UserPtr::new(usage).write(&get_memory_usage()) {
+        Ok(()) => 0,
+        Err(_) => -1,
     }
-    -1  //  失败
 }
 
+/// Fill `buf` with up to `len` areas of the current task in address order,
+/// returning how many areas there are in total.
+pub fn sys_task_maps(buf: *mut VmaInfo, len: usize) -> isize {
+    let vmas = get_current_vmas();
+    for (i, vma) in vmas.iter().take(len).enumerate() {
+        if UserPtr::new(buf.wrapping_add(i)).write(vma).is_err() {
+            return -1;
+        }
+    }
+    vmas.len() as isize
+}
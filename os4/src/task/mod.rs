@@ -1,38 +1,162 @@
 mod context;
+mod pid;
+mod scheduler;
 mod switch;
 #[allow(clippy::module_inception)]
 mod task;
 
-use crate::config::{MAX_SYSCALL_NUM};
-use crate::loader::{};
+use crate::config::{MAX_SYSCALL_NUM, SWAP_WATERMARK};
 use crate::sync::UPSafeCell;
 use crate::trap::TrapContext;
-use crate::timer::get_time_us;
+use crate::timer::{get_time_us, set_next_trigger};
 use core::arch::asm;
+use core::sync::atomic::{AtomicBool, Ordering};
 use lazy_static::*;
+use alloc::vec;
 use alloc::vec::Vec;
-use crate::loader::{get_num_app, get_app_data};
-use crate::mm::{VirtAddr, MapPermission};
+use alloc::boxed::Box;
+use alloc::collections::BinaryHeap;
+use core::cmp::Reverse;
+use crate::loader::{get_num_app, get_app_data, get_app_name};
+use crate::fs::File;
+use alloc::sync::Arc;
+use crate::mm::{VirtAddr, VirtPageNum, MapPermission, MmapFlags, FileMapping, MemoryUsage, VmaInfo, AccessType, MemorySet, ElfError, SlabBox, TASK_CACHE, frame_stats, shm_frames, shm_collect};
 
 pub use switch::__switch;
 pub use task::{TaskControlBlock, TaskStatus};
 pub use context::TaskContext;
+use scheduler::{new_scheduler, Scheduler};
 
 pub struct TaskManager {
-    num_app: usize,
     inner: UPSafeCell<TaskManagerInner>,
 }
 
 
 struct TaskManagerInner {
-    tasks: Vec<TaskControlBlock>,
+    /// tasks by slot, a slot is freed when its task is reaped and the
+    /// scheduler knows tasks by their slot
+    tasks: Vec<Option<SlabBox<TaskControlBlock>>>,
     current_task: usize,
+    /// pid of the init process, which adopts orphans
+    init_pid: Option<usize>,
+    /// decides which `Ready` task runs next
+    scheduler: Box<dyn Scheduler>,
+    /// sleeping tasks by wakeup time in microseconds, the earliest on top
+    sleepers: BinaryHeap<Reverse<(usize, usize)>>,
+    /// clock hand of the swap policy: the task and the page to look at next
+    swap_hand: (usize, VirtPageNum),
 }
 
+impl TaskManagerInner {
+    fn task(&self, slot: usize) -> &TaskControlBlock {
+        self.tasks[slot].as_ref().unwrap()
+    }
+
+    fn task_mut(&mut self, slot: usize) -> &mut TaskControlBlock {
+        self.tasks[slot].as_mut().unwrap()
+    }
+
+    fn slot_of(&self, pid: usize) -> Option<usize> {
+        self.tasks
+            .iter()
+            .position(|task| task.as_ref().map_or(false, |task| task.pid.0 == pid))
+    }
+
+    /// Put `task` in the first free slot.
+    fn add_task(&mut self, task: TaskControlBlock) -> usize {
+        let task = Box::new_in(task, &TASK_CACHE);
+        match self.tasks.iter().position(|task| task.is_none()) {
+            Some(slot) => {
+                self.tasks[slot] = Some(task);
+                slot
+            }
+            None => {
+                self.tasks.push(Some(task));
+                self.tasks.len() - 1
+            }
+        }
+    }
+
+    /// Hand the tasks whose wakeup time has come back to the scheduler.
+    fn wake_sleepers(&mut self) {
+        let now = get_time_us();
+        while let Some(Reverse((deadline, slot))) = self.sleepers.peek().copied() {
+            if deadline > now {
+                break;
+            }
+            self.sleepers.pop();
+            let task = self.task_mut(slot);
+            task.task_status = TaskStatus::Ready;
+            let priority = task.priority;
+            self.scheduler.add(slot, priority);
+        }
+    }
+
+    /// Free the slots of exited tasks nobody will wait for. The current task
+    /// is still on its kernel stack, so it is left for a later call.
+    fn reap_orphans(&mut self) {
+        let current = self.current_task;
+        for (slot, task) in self.tasks.iter_mut().enumerate() {
+            if slot != current
+                && task.as_ref().map_or(false, |task| {
+                    task.task_status == TaskStatus::Exited && task.parent.is_none()
+                })
+            {
+                *task = None;
+            }
+        }
+    }
+
+    /// Swap out user pages of any task until `SWAP_WATERMARK` frames are
+    /// free, giving up after two sweeps over all tasks. Whether any frame
+    /// was freed.
+    fn reclaim(&mut self) -> bool {
+        let free = frame_stats().free;
+        let num_task = self.tasks.len();
+        // the first sweep may only clear accessed bits
+        let mut moves = 2 * num_task + 1;
+        while frame_stats().free < SWAP_WATERMARK && moves > 0 {
+            let (task, from) = self.swap_hand;
+            if let Some(tcb) = self.tasks[task].as_mut() {
+                if tcb.task_status != TaskStatus::Exited {
+                    if let Some(vpn) = tcb.memory_set.swap_out(from) {
+                        self.swap_hand = (task, VirtPageNum(vpn.0 + 1));
+                        continue;
+                    }
+                }
+            }
+            self.swap_hand = ((task + 1) % num_task, VirtPageNum(0));
+            moves -= 1;
+        }
+        frame_stats().free > free
+    }
+
+    /// Run `op` on the current task, again after swapping out pages each
+    /// time it runs out of frames. Allocations made while the task manager
+    /// is borrowed cannot reclaim by themselves.
+    fn retry_current<T>(
+        &mut self,
+        mut op: impl FnMut(&mut TaskControlBlock) -> Result<T, ElfError>,
+    ) -> Result<T, ElfError> {
+        let current = self.current_task;
+        loop {
+            match op(self.task_mut(current)) {
+                Err(ElfError::OutOfMemory) if self.reclaim() => continue,
+                ret => return ret,
+            }
+        }
+    }
+}
+
+/// set once the first task runs, so that the panic handler knows whether
+/// `TASK_MANAGER` may be touched
+static TASKS_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// what sys_task_info writes to the user
 pub struct TaskInfo {
-    status: TaskStatus,
-    syscall_times: [u32; MAX_SYSCALL_NUM],
-    time: usize,
+    pub status: TaskStatus,
+    pub syscall_times: [u32; MAX_SYSCALL_NUM],
+    pub time: usize,
 }
 
 lazy_static! {
@@ -41,20 +165,40 @@ lazy_static! {
         println!("init TASK_MANAGER");
         let num_app = get_num_app();
         println!("num_app = {}", num_app);
-        let mut tasks : Vec<TaskControlBlock> = Vec::new();
-        for i in 0..num_app {
+        // with an init process only that one is started and it runs the
+        // other apps, else all of them are started as a batch
+        let init_app = (0..num_app).find(|i| get_app_name(*i).ends_with("initproc"));
+        let boot_apps: Vec<usize> = match init_app {
+            Some(i) => vec![i],
+            None => (0..num_app).collect(),
+        };
+        let mut tasks : Vec<Option<SlabBox<TaskControlBlock>>> = Vec::new();
+        for i in boot_apps {
             // println!("{}th app's tcb is being created",i);
-            tasks.push(TaskControlBlock::new(
-                get_app_data(i),
-                i,
-            ));
+            match TaskControlBlock::new(get_app_data(i)) {
+                Ok(task) => tasks.push(Some(Box::new_in(task, &TASK_CACHE))),
+                Err(err) => error!("[kernel] app {} is not loaded: {:?}", get_app_name(i), err),
+            }
+        }
+        // apps failing to load are left out
+        let init_pid = match (init_app, tasks.first()) {
+            (Some(_), Some(Some(task))) => Some(task.pid.0),
+            _ => None,
+        };
+        let mut scheduler = new_scheduler();
+        println!("[kernel] {} scheduler", scheduler.name());
+        for (slot, task) in tasks.iter().enumerate() {
+            scheduler.add(slot, task.as_ref().unwrap().priority);
         }
         TaskManager {
-            num_app,
             inner: unsafe {
                 UPSafeCell::new(TaskManagerInner {
                     tasks,
                     current_task: 0,
+                    init_pid,
+                    scheduler,
+                    sleepers: BinaryHeap::new(),
+                    swap_hand: (0, VirtPageNum(0)),
                 })
             },
         }
@@ -67,8 +211,11 @@ impl TaskManager {
     /// Generally, the first task in task list is an idle task (we call it zero process later).
     /// But in ch3, we load apps statically, so the first task is a real app.
     fn run_first_task(&self) -> ! {
+        TASKS_STARTED.store(true, Ordering::Relaxed);
         let mut inner = self.inner.exclusive_access();
-        let task0 = &mut inner.tasks[0];
+        let first = inner.scheduler.pick_next().expect("no app to run");
+        inner.current_task = first;
+        let task0 = inner.task_mut(first);
         task0.task_status = TaskStatus::Running;
         let next_task_cx_ptr = &task0.task_cx as *const TaskContext;
         drop(inner);
@@ -80,29 +227,85 @@ impl TaskManager {
         panic!("unreachable in run_first_task!");
     }
 
-    /// Change the status of current `Running` task into `Ready`.
-    fn mark_current_suspended(&self) {
+    /// Change the status of current `Running` task into `Ready`, `preempted`
+    /// tells whether the timer took the CPU from it.
+    fn mark_current_suspended(&self, preempted: bool) {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        inner.task_mut(current).task_status = TaskStatus::Ready;
+        if preempted {
+            inner.scheduler.on_tick(current);
+        } else {
+            inner.scheduler.on_yield(current);
+        }
+        let priority = inner.task(current).priority;
+        inner.scheduler.add(current, priority);
+    }
+
+    /// Change the status of current `Running` task into `Blocked` until
+    /// `deadline` in microseconds.
+    fn mark_current_sleeping(&self, deadline: usize) {
         let mut inner = self.inner.exclusive_access();
         let current = inner.current_task;
-        inner.tasks[current].task_status = TaskStatus::Ready;
+        inner.task_mut(current).task_status = TaskStatus::Blocked;
+        inner.scheduler.on_yield(current);
+        inner.sleepers.push(Reverse((deadline, current)));
     }
 
     /// Change the status of current `Running` task into `Exited`.
-    fn mark_current_exited(&self) {
+    ///
+    /// Its memory and files are released at once, the task stays a zombie
+    /// until its parent reaps it. Its children go to the init process, or
+    /// have no parent if there is none.
+    fn mark_current_exited(&self, exit_code: i32) {
         let mut inner = self.inner.exclusive_access();
+        inner.reap_orphans();
         let current = inner.current_task;
-        inner.tasks[current].task_status = TaskStatus::Exited;
+        inner.scheduler.remove(current);
+        let task = inner.task_mut(current);
+        task.task_status = TaskStatus::Exited;
+        task.exit_code = exit_code;
+        // unmapping writes back what was written to files mapped shared
+        task.memory_set.recycle_data_pages();
+        task.fd_table.clear();
+        let pid = task.pid.0;
+        let children = core::mem::take(&mut task.children);
+        if inner.init_pid == Some(pid) {
+            inner.init_pid = None;
+        }
+        let init_pid = inner.init_pid;
+        for child in children.iter() {
+            let slot = inner.slot_of(*child).unwrap();
+            inner.task_mut(slot).parent = init_pid;
+        }
+        match init_pid.and_then(|init| inner.slot_of(init)) {
+            Some(init) => inner.task_mut(init).children.extend(children),
+            None => inner.reap_orphans(),
+        }
     }
 
     /// Find next task to run and return task id.
     ///
-    /// In this case, we only return the first `Ready` task in task list.
+    /// The scheduler picks among the `Ready` tasks. While there is none but
+    /// some task sleeps, the hart waits for the timer.
     fn find_next_task(&self) -> Option<usize> {
-        let inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        (current + 1..current + self.num_app + 1)
-            .map(|id| id % self.num_app)
-            .find(|id| inner.tasks[*id].task_status == TaskStatus::Ready)
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            inner.wake_sleepers();
+            if let Some(next) = inner.scheduler.pick_next() {
+                return Some(next);
+            }
+            if inner.sleepers.is_empty() {
+                return None;
+            }
+            drop(inner);
+            // interrupts are off in the kernel, but a pending timer
+            // interrupt still ends wfi
+            unsafe {
+                asm!("wfi");
+            }
+            set_next_trigger();
+        }
     }
 
     /// Switch current `Running` task to the task we have found,
@@ -111,13 +314,13 @@ impl TaskManager {
         if let Some(next) = self.find_next_task() {
             let mut inner = self.inner.exclusive_access();
             let current = inner.current_task;
-            inner.tasks[next].task_status = TaskStatus::Running;
-            if inner.tasks[next].task_start_time==0 {
-                inner.tasks[next].task_start_time = get_time_us();
+            inner.task_mut(next).task_status = TaskStatus::Running;
+            if inner.task(next).task_start_time==0 {
+                inner.task_mut(next).task_start_time = get_time_us();
             }
             inner.current_task = next;
-            let current_task_cx_ptr = &mut inner.tasks[current].task_cx as *mut TaskContext;
-            let next_task_cx_ptr = &inner.tasks[next].task_cx as *const TaskContext;
+            let current_task_cx_ptr = &mut inner.task_mut(current).task_cx as *mut TaskContext;
+            let next_task_cx_ptr = &inner.task(next).task_cx as *const TaskContext;
             drop(inner);
             // before this, we should drop local variables that must be dropped manually
             unsafe {
@@ -130,53 +333,220 @@ impl TaskManager {
     }
 
     fn get_current_token(&self) -> usize {
-        let inner = self.inner.exclusive_access();
+        let mut inner = self.inner.exclusive_access();
         let current = inner.current_task;
-        inner.tasks[current].get_user_token()
+        inner.task_mut(current).get_user_token()
     }
 
     fn get_current_trap_cx(&self) -> &mut TrapContext {
         let inner = self.inner.exclusive_access();
         let current = inner.current_task;
-        inner.tasks[current].get_trap_cx()
+        inner.task(current).get_trap_cx()
     }
 
-    fn get_current_task_info(&self, ti:*mut TaskInfo) -> usize {
+    fn get_current_task_info(&self) -> TaskInfo {
         let inner = self.inner.exclusive_access();
         let current_task = inner.current_task;
-        unsafe{
-            *ti = TaskInfo{
-                status: inner.tasks[current_task].task_status,
-                syscall_times: inner.tasks[current_task].task_syscall_times,
-                time: (get_time_us() - inner.tasks[current_task].task_start_time) / 1000,
-            }
+        TaskInfo{
+            status: inner.task(current_task).task_status,
+            syscall_times: inner.task(current_task).task_syscall_times,
+            time: (get_time_us() - inner.task(current_task).task_start_time) / 1000,
         }
-        0
+    }
+
+    fn get_current_memory_usage(&self) -> MemoryUsage {
+        let inner = self.inner.exclusive_access();
+        let current_task = inner.current_task;
+        inner.task(current_task).memory_set.usage()
+    }
+
+    fn get_current_vmas(&self) -> Vec<VmaInfo> {
+        let inner = self.inner.exclusive_access();
+        let current_task = inner.current_task;
+        inner.task(current_task).memory_set.vmas()
     }
 
     fn increase_task_syscall(&self, syscall_id: usize) {
         let mut inner = self.inner.exclusive_access();
         let current_task = inner.current_task;
-        inner.tasks[current_task].task_syscall_times[syscall_id] += 1;
+        inner.task_mut(current_task).task_syscall_times[syscall_id] += 1;
     }
     
-    fn current_m_map(&self, start: VirtAddr, len: usize, perm: MapPermission) -> isize{
+    fn current_m_map(
+        &self,
+        start: VirtAddr,
+        len: usize,
+        perm: MapPermission,
+        flags: MmapFlags,
+        file: Option<FileMapping>,
+    ) -> isize{
         let mut inner = self.inner.exclusive_access();
         let current_task = inner.current_task;
-        inner.tasks[current_task].memory_set.mmap(start, len, perm)
+        let ret = inner.task_mut(current_task).memory_set.mmap(start, len, perm, flags, file);
+        // MAP_FIXED may have replaced shared memory
+        shm_collect();
+        ret
     }
 
     fn current_m_unmap(&self, start: VirtAddr, len: usize) -> isize {
         let mut inner = self.inner.exclusive_access();
         let current_task = inner.current_task;
-        inner.tasks[current_task].memory_set.munmap(start, len)
+        let ret = inner.task_mut(current_task).memory_set.munmap(start, len);
+        // the range may have covered shared memory
+        shm_collect();
+        ret
     }
 
-    fn get_curren_task_note(&self) -> usize {
+    fn current_brk(&self, addr: usize) -> usize {
         let mut inner = self.inner.exclusive_access();
         let current_task = inner.current_task;
-        drop(inner);
-        current_task
+        inner.task_mut(current_task).memory_set.brk(addr)
+    }
+
+    fn current_m_sync(&self, start: VirtAddr, len: usize) -> isize {
+        let mut inner = self.inner.exclusive_access();
+        let current_task = inner.current_task;
+        inner.task_mut(current_task).memory_set.msync(start, len)
+    }
+
+    fn current_file(&self, fd: usize) -> Option<Arc<dyn File>> {
+        let inner = self.inner.exclusive_access();
+        let current_task = inner.current_task;
+        inner.task(current_task).fd_table.get(fd)?.clone()
+    }
+
+    fn current_add_file(&self, file: Arc<dyn File>) -> usize {
+        let mut inner = self.inner.exclusive_access();
+        let current_task = inner.current_task;
+        let task = inner.task_mut(current_task);
+        let fd = task.alloc_fd();
+        task.fd_table[fd] = Some(file);
+        fd
+    }
+
+    fn current_close_file(&self, fd: usize) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        let current_task = inner.current_task;
+        match inner.task_mut(current_task).fd_table.get_mut(fd) {
+            Some(file) => file.take().is_some(),
+            None => false,
+        }
+    }
+
+    fn current_m_protect(&self, start: VirtAddr, len: usize, perm: MapPermission) -> isize {
+        let mut inner = self.inner.exclusive_access();
+        let current_task = inner.current_task;
+        inner.task_mut(current_task).memory_set.mprotect(start, len, perm)
+    }
+
+    fn current_shm_attach(&self, key: usize, start: VirtAddr, perm: MapPermission) -> isize {
+        let frames = match shm_frames(key) {
+            Some(frames) => frames,
+            None => return -1,
+        };
+        let mut inner = self.inner.exclusive_access();
+        let current_task = inner.current_task;
+        let ret = inner.task_mut(current_task).memory_set.attach_shared(start, &frames, perm);
+        drop(frames);
+        shm_collect();
+        ret
+    }
+
+    fn current_shm_detach(&self, start: VirtAddr) -> isize {
+        let mut inner = self.inner.exclusive_access();
+        let current_task = inner.current_task;
+        let ret = inner.task_mut(current_task).memory_set.detach_shared(start);
+        shm_collect();
+        ret
+    }
+
+    fn handle_current_page_fault(&self, va: VirtAddr, access: AccessType) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        inner.reclaim();
+        let current_task = inner.current_task;
+        inner.task_mut(current_task).memory_set.handle_page_fault(va, access)
+    }
+
+    fn with_current_memory_set<T>(&self, f: impl FnOnce(&mut MemorySet) -> T) -> T {
+        let mut inner = self.inner.exclusive_access();
+        inner.reclaim();
+        let current_task = inner.current_task;
+        f(&mut inner.task_mut(current_task).memory_set)
+    }
+
+    fn fork_current(&self) -> isize {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        let child = match inner.retry_current(|task| task.fork()) {
+            Ok(child) => child,
+            Err(_) => return -1,
+        };
+        let (pid, priority) = (child.pid.0, child.priority);
+        let slot = inner.add_task(child);
+        inner.task_mut(current).children.push(pid);
+        inner.scheduler.add(slot, priority);
+        pid as isize
+    }
+
+    fn spawn_current(&self, elf_data: &[u8]) -> isize {
+        let mut child = match TaskControlBlock::new(elf_data) {
+            Ok(child) => child,
+            Err(_) => return -1,
+        };
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        child.parent = Some(inner.task(current).pid.0);
+        let (pid, priority) = (child.pid.0, child.priority);
+        let slot = inner.add_task(child);
+        inner.task_mut(current).children.push(pid);
+        inner.scheduler.add(slot, priority);
+        pid as isize
+    }
+
+    fn exec_current(&self, elf_data: &[u8]) -> isize {
+        let mut inner = self.inner.exclusive_access();
+        match inner.retry_current(|task| task.exec(elf_data)) {
+            Ok(()) => 0,
+            Err(_) => -1,
+        }
+    }
+
+    fn waitpid_current(&self, pid: isize) -> (isize, i32) {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        let children: Vec<usize> = inner
+            .task(current)
+            .children
+            .iter()
+            .copied()
+            .filter(|child| pid == -1 || *child as isize == pid)
+            .collect();
+        if children.is_empty() {
+            return (-1, 0);
+        }
+        let zombie = children
+            .iter()
+            .filter_map(|child| inner.slot_of(*child))
+            .find(|slot| inner.task(*slot).task_status == TaskStatus::Exited);
+        match zombie {
+            Some(slot) => {
+                let child = inner.tasks[slot].take().unwrap();
+                inner.task_mut(current).children.retain(|pid| *pid != child.pid.0);
+                (child.pid.0 as isize, child.exit_code)
+            }
+            None => (-2, 0),
+        }
+    }
+
+    fn get_current_pid(&self) -> usize {
+        let inner = self.inner.exclusive_access();
+        inner.task(inner.current_task).pid.0
+    }
+
+    fn set_current_priority(&self, priority: usize) {
+        let mut inner = self.inner.exclusive_access();
+        let current_task = inner.current_task;
+        inner.task_mut(current_task).priority = priority;
     }
 }
 
@@ -190,26 +560,35 @@ fn run_next_task() {
     TASK_MANAGER.run_next_task();
 }
 
-fn mark_current_suspended() {
-    TASK_MANAGER.mark_current_suspended();
+fn mark_current_suspended(preempted: bool) {
+    TASK_MANAGER.mark_current_suspended(preempted);
 }
 
-fn mark_current_exited() {
-    TASK_MANAGER.mark_current_exited();
+fn mark_current_exited(exit_code: i32) {
+    TASK_MANAGER.mark_current_exited(exit_code);
 }
 
+/// The current task yields the CPU.
 pub fn suspend_current_and_run_next() {
-    mark_current_suspended();
+    mark_current_suspended(false);
     run_next_task();
 }
 
-pub fn exit_current_and_run_next() {
-    mark_current_exited();
+/// The timer takes the CPU from the current task.
+pub fn preempt_current_and_run_next() {
+    mark_current_suspended(true);
     run_next_task();
 }
 
-pub fn current_task_note() -> usize {
-    TASK_MANAGER.get_curren_task_note()
+/// Block the current task for `ms` milliseconds.
+pub fn sleep_current_and_run_next(ms: usize) {
+    TASK_MANAGER.mark_current_sleeping(get_time_us() + ms * 1000);
+    run_next_task();
+}
+
+pub fn exit_current_and_run_next(exit_code: i32) {
+    mark_current_exited(exit_code);
+    run_next_task();
 }
 
 pub fn current_user_token() -> usize {
@@ -220,18 +599,139 @@ pub fn current_trap_cx() -> &'static mut TrapContext {
     TASK_MANAGER.get_current_trap_cx()
 }
 
-pub fn get_task_info(ti: *mut TaskInfo) -> usize {
-    TASK_MANAGER.get_current_task_info(ti)
+pub fn get_task_info() -> TaskInfo {
+    TASK_MANAGER.get_current_task_info()
+}
+
+/// Resident pages and page limit of the current task.
+pub fn get_memory_usage() -> MemoryUsage {
+    TASK_MANAGER.get_current_memory_usage()
+}
+
+/// Areas of the current task in address order.
+pub fn get_current_vmas() -> Vec<VmaInfo> {
+    TASK_MANAGER.get_current_vmas()
+}
+
+/// Swap out user pages because an allocation ran out of frames, `false` if
+/// none could be freed. Left to the caller while the task manager is in use.
+pub fn reclaim_frames() -> bool {
+    if !TASKS_STARTED.load(Ordering::Relaxed) {
+        return false;
+    }
+    TASK_MANAGER
+        .inner
+        .try_access()
+        .map_or(false, |mut inner| inner.reclaim())
+}
+
+/// Print the areas of the current task, if tasks are running and the task
+/// manager is not in use, so that it is safe to call while panicking.
+pub fn dump_current_memory_set() {
+    if !TASKS_STARTED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Some(inner) = TASK_MANAGER.inner.try_access() {
+        if let Some(task) = inner.tasks[inner.current_task].as_ref() {
+            println!("[kernel] address space of task {}", task.pid.0);
+            task.memory_set.dump();
+        }
+    }
+}
+
+/// Set the priority of the current task, which must be at least 2.
+pub fn set_current_priority(priority: usize) {
+    TASK_MANAGER.set_current_priority(priority);
+}
+
+pub fn current_pid() -> usize {
+    TASK_MANAGER.get_current_pid()
+}
+
+/// Copy the current task, returning the pid of the child.
+pub fn fork_current() -> isize {
+    TASK_MANAGER.fork_current()
+}
+
+/// Start the app in `elf_data` as a child of the current task, returning
+/// its pid or -1.
+pub fn spawn_current(elf_data: &[u8]) -> isize {
+    TASK_MANAGER.spawn_current(elf_data)
+}
+
+/// Run the app in `elf_data` in the current task, -1 if it does not load.
+pub fn exec_current(elf_data: &[u8]) -> isize {
+    TASK_MANAGER.exec_current(elf_data)
+}
+
+/// Reap an exited child of the current task with `pid`, any child if -1.
+/// Returns its pid and exit code, -1 if there is no such child and -2 if it
+/// is still running.
+pub fn waitpid_current(pid: isize) -> (isize, i32) {
+    TASK_MANAGER.waitpid_current(pid)
 }
 
 pub fn increase_task_syscall_times(syscall_id: usize) {
     TASK_MANAGER.increase_task_syscall(syscall_id);
 }
 
-pub fn current_mmap(start: VirtAddr, len: usize, perm: MapPermission) -> isize {
-    TASK_MANAGER.current_m_map(start, len, perm)
+/// Map anonymous memory or `file` in the current task, returning its
+/// address or -1.
+pub fn current_mmap(
+    start: VirtAddr,
+    len: usize,
+    perm: MapPermission,
+    flags: MmapFlags,
+    file: Option<FileMapping>,
+) -> isize {
+    TASK_MANAGER.current_m_map(start, len, perm, flags, file)
+}
+
+/// Move the program break of the current task, returning the new break.
+pub fn current_brk(addr: usize) -> usize {
+    TASK_MANAGER.current_brk(addr)
+}
+
+pub fn current_msync(start: VirtAddr, len: usize) -> isize {
+    TASK_MANAGER.current_m_sync(start, len)
+}
+
+/// The file open as `fd` in the current task.
+pub fn current_file(fd: usize) -> Option<Arc<dyn File>> {
+    TASK_MANAGER.current_file(fd)
+}
+
+/// Install `file` in the lowest free fd of the current task.
+pub fn current_add_file(file: Arc<dyn File>) -> usize {
+    TASK_MANAGER.current_add_file(file)
+}
+
+/// Close `fd` of the current task, false if it was not open.
+pub fn current_close_file(fd: usize) -> bool {
+    TASK_MANAGER.current_close_file(fd)
 }
 
 pub fn current_munmap(start: VirtAddr, len: usize) -> isize {
     TASK_MANAGER.current_m_unmap(start, len)
-}
\ No newline at end of file
+}
+
+pub fn current_mprotect(start: VirtAddr, len: usize, perm: MapPermission) -> isize {
+    TASK_MANAGER.current_m_protect(start, len, perm)
+}
+
+pub fn current_shm_attach(key: usize, start: VirtAddr, perm: MapPermission) -> isize {
+    TASK_MANAGER.current_shm_attach(key, start, perm)
+}
+
+pub fn current_shm_detach(start: VirtAddr) -> isize {
+    TASK_MANAGER.current_shm_detach(start)
+}
+
+pub fn handle_page_fault(va: VirtAddr, access: AccessType) -> bool {
+    TASK_MANAGER.handle_current_page_fault(va, access)
+}
+
+/// Run `f` on the address space of the current task.
+pub fn with_current_memory_set<T>(f: impl FnOnce(&mut MemorySet) -> T) -> T {
+    TASK_MANAGER.with_current_memory_set(f)
+}
@@ -0,0 +1,87 @@
+//! Process identifiers and the kernel stacks placed by them
+
+use crate::config::kernel_stack_position;
+use crate::mm::{ElfError, MapPermission, VirtAddr, KERNEL_SPACE};
+use crate::sync::UPSafeCell;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// Hands out the lowest ids first, reusing released ones.
+struct PidAllocator {
+    current: usize,
+    recycled: Vec<usize>,
+}
+
+impl PidAllocator {
+    fn alloc(&mut self) -> usize {
+        if let Some(pid) = self.recycled.pop() {
+            pid
+        } else {
+            self.current += 1;
+            self.current - 1
+        }
+    }
+
+    fn dealloc(&mut self, pid: usize) {
+        assert!(pid < self.current);
+        assert!(!self.recycled.contains(&pid), "pid {} has been deallocated!", pid);
+        self.recycled.push(pid);
+    }
+}
+
+lazy_static! {
+    static ref PID_ALLOCATOR: UPSafeCell<PidAllocator> = unsafe {
+        UPSafeCell::new(PidAllocator {
+            current: 0,
+            recycled: Vec::new(),
+        })
+    };
+}
+
+/// A pid, released when dropped.
+pub struct PidHandle(pub usize);
+
+impl Drop for PidHandle {
+    fn drop(&mut self) {
+        PID_ALLOCATOR.exclusive_access().dealloc(self.0);
+    }
+}
+
+pub fn pid_alloc() -> PidHandle {
+    PidHandle(PID_ALLOCATOR.exclusive_access().alloc())
+}
+
+/// Kernel stack of a task, mapped in kernel space at a position given by
+/// its pid and unmapped when dropped.
+pub struct KernelStack {
+    pid: usize,
+}
+
+impl KernelStack {
+    /// Map the stack of `pid_handle`, `ElfError::OutOfMemory` if frames ran
+    /// out.
+    pub fn new(pid_handle: &PidHandle) -> Result<Self, ElfError> {
+        let pid = pid_handle.0;
+        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(pid);
+        KERNEL_SPACE.exclusive_access().insert_frame_area(
+            kernel_stack_bottom.into(),
+            kernel_stack_top.into(),
+            MapPermission::R | MapPermission::W,
+        )?;
+        Ok(KernelStack { pid })
+    }
+
+    pub fn get_top(&self) -> usize {
+        kernel_stack_position(self.pid).1
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let (kernel_stack_bottom, _) = kernel_stack_position(self.pid);
+        let kernel_stack_bottom_va: VirtAddr = kernel_stack_bottom.into();
+        KERNEL_SPACE
+            .exclusive_access()
+            .remove_area_with_start_vpn(kernel_stack_bottom_va.into());
+    }
+}
@@ -0,0 +1,73 @@
+//! Lottery scheduling: each ready task holds as many tickets as its priority
+//! and the next task is drawn at random among the tickets.
+
+use super::Scheduler;
+use crate::timer::get_time;
+use alloc::vec::Vec;
+
+/// keeps the sum of the tickets from overflowing
+const MAX_TICKETS: usize = 1 << 20;
+
+pub struct LotteryScheduler {
+    /// ready tasks and their tickets
+    ready: Vec<(usize, usize)>,
+    /// xorshift state
+    seed: u64,
+}
+
+impl LotteryScheduler {
+    pub fn new() -> Self {
+        Self {
+            ready: Vec::new(),
+            seed: get_time() as u64 | 1,
+        }
+    }
+
+    fn random(&mut self) -> u64 {
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.seed = x;
+        x
+    }
+}
+
+impl Scheduler for LotteryScheduler {
+    fn name(&self) -> &'static str {
+        "lottery"
+    }
+
+    fn add(&mut self, id: usize, priority: usize) {
+        self.ready.push((id, priority.min(MAX_TICKETS)));
+    }
+
+    fn remove(&mut self, id: usize) {
+        self.ready.retain(|(ready, _)| *ready != id);
+    }
+
+    fn pick_next(&mut self) -> Option<usize> {
+        let total: usize = self.ready.iter().map(|(_, tickets)| tickets).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut winner = self.random() as usize % total;
+        let index = self
+            .ready
+            .iter()
+            .position(|(_, tickets)| {
+                if winner < *tickets {
+                    true
+                } else {
+                    winner -= tickets;
+                    false
+                }
+            })
+            .unwrap();
+        Some(self.ready.swap_remove(index).0)
+    }
+
+    fn on_tick(&mut self, _id: usize) {}
+
+    fn on_yield(&mut self, _id: usize) {}
+}
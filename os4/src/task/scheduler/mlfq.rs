@@ -0,0 +1,88 @@
+//! Multi-level feedback queue
+//!
+//! New tasks start at the top level. A task is moved one level down once it
+//! has been preempted `ALLOTMENT << level` times at its level, while yielding
+//! keeps its level, so interactive tasks stay on top. Every `BOOST_TICKS`
+//! preemptions all tasks go back to the top so that none starves.
+
+use super::Scheduler;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+const LEVELS: usize = 3;
+const ALLOTMENT: usize = 2;
+const BOOST_TICKS: usize = 100;
+
+#[derive(Copy, Clone, Default)]
+struct Task {
+    level: usize,
+    /// preemptions at the current level
+    used: usize,
+}
+
+pub struct MlfqScheduler {
+    queues: [VecDeque<usize>; LEVELS],
+    tasks: Vec<Task>,
+    ticks: usize,
+}
+
+impl MlfqScheduler {
+    pub fn new() -> Self {
+        Self {
+            queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            tasks: Vec::new(),
+            ticks: 0,
+        }
+    }
+
+    fn boost(&mut self) {
+        for task in self.tasks.iter_mut() {
+            *task = Task::default();
+        }
+        for level in 1..LEVELS {
+            let queue = core::mem::take(&mut self.queues[level]);
+            self.queues[0].extend(queue);
+        }
+    }
+}
+
+impl Scheduler for MlfqScheduler {
+    fn name(&self) -> &'static str {
+        "multi-level feedback queue"
+    }
+
+    fn add(&mut self, id: usize, _priority: usize) {
+        if id >= self.tasks.len() {
+            self.tasks.resize(id + 1, Task::default());
+        }
+        self.queues[self.tasks[id].level].push_back(id);
+    }
+
+    fn remove(&mut self, id: usize) {
+        if let Some(task) = self.tasks.get_mut(id) {
+            *task = Task::default();
+        }
+        for queue in self.queues.iter_mut() {
+            queue.retain(|ready| *ready != id);
+        }
+    }
+
+    fn pick_next(&mut self) -> Option<usize> {
+        self.queues.iter_mut().find_map(|queue| queue.pop_front())
+    }
+
+    fn on_tick(&mut self, id: usize) {
+        let task = &mut self.tasks[id];
+        task.used += 1;
+        if task.used >= ALLOTMENT << task.level && task.level + 1 < LEVELS {
+            task.level += 1;
+            task.used = 0;
+        }
+        self.ticks += 1;
+        if self.ticks % BOOST_TICKS == 0 {
+            self.boost();
+        }
+    }
+
+    fn on_yield(&mut self, _id: usize) {}
+}
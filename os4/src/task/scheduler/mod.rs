@@ -0,0 +1,46 @@
+//! Scheduling policies
+//!
+//! The task manager only tells the scheduler which tasks are ready and how
+//! the running one gave up the CPU, the policy decides who runs next. It is
+//! chosen at build time with one of the `sched-rr`, `sched-mlfq` and
+//! `sched-lottery` features, stride scheduling is the default.
+
+mod lottery;
+mod mlfq;
+mod round_robin;
+mod stride;
+
+use alloc::boxed::Box;
+
+pub use lottery::LotteryScheduler;
+pub use mlfq::MlfqScheduler;
+pub use round_robin::RoundRobinScheduler;
+pub use stride::StrideScheduler;
+
+/// A scheduling policy over task ids.
+pub trait Scheduler: Send {
+    fn name(&self) -> &'static str;
+    /// Task `id` of `priority` became `Ready`.
+    fn add(&mut self, id: usize, priority: usize);
+    /// Forget task `id`, it will not run again.
+    fn remove(&mut self, id: usize);
+    /// Take the next task to run off the ready tasks.
+    fn pick_next(&mut self) -> Option<usize>;
+    /// Task `id` was preempted by the timer, called before it is added back.
+    fn on_tick(&mut self, id: usize);
+    /// Task `id` gave up the CPU on its own, called before it is added back.
+    fn on_yield(&mut self, id: usize);
+}
+
+/// The policy selected by the cargo features.
+pub fn new_scheduler() -> Box<dyn Scheduler> {
+    if cfg!(feature = "sched-rr") {
+        Box::new(RoundRobinScheduler::new())
+    } else if cfg!(feature = "sched-mlfq") {
+        Box::new(MlfqScheduler::new())
+    } else if cfg!(feature = "sched-lottery") {
+        Box::new(LotteryScheduler::new())
+    } else {
+        Box::new(StrideScheduler::new())
+    }
+}
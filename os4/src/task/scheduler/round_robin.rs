@@ -0,0 +1,36 @@
+//! Round robin: ready tasks run in the order they became ready.
+
+use super::Scheduler;
+use alloc::collections::VecDeque;
+
+pub struct RoundRobinScheduler {
+    ready: VecDeque<usize>,
+}
+
+impl RoundRobinScheduler {
+    pub fn new() -> Self {
+        Self { ready: VecDeque::new() }
+    }
+}
+
+impl Scheduler for RoundRobinScheduler {
+    fn name(&self) -> &'static str {
+        "round robin"
+    }
+
+    fn add(&mut self, id: usize, _priority: usize) {
+        self.ready.push_back(id);
+    }
+
+    fn remove(&mut self, id: usize) {
+        self.ready.retain(|ready| *ready != id);
+    }
+
+    fn pick_next(&mut self) -> Option<usize> {
+        self.ready.pop_front()
+    }
+
+    fn on_tick(&mut self, _id: usize) {}
+
+    fn on_yield(&mut self, _id: usize) {}
+}
@@ -0,0 +1,91 @@
+//! Stride scheduling
+//!
+//! Every time a task is scheduled its pass grows by `BIG_STRIDE / priority`,
+//! and the ready task with the smallest pass runs next. Passes are allowed to
+//! wrap: as priorities are at least 2, ready passes never lie more than
+//! `BIG_STRIDE / 2` apart, so the sign of their wrapping difference orders
+//! them.
+
+use super::Scheduler;
+use crate::config::BIG_STRIDE;
+use alloc::collections::BinaryHeap;
+use alloc::vec::Vec;
+use core::cmp::{Ordering, Reverse};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Pass(pub usize);
+
+impl Pass {
+    /// Advance by the stride of `priority`.
+    pub fn step(&mut self, priority: usize) {
+        self.0 = self.0.wrapping_add((BIG_STRIDE / priority).max(1));
+    }
+}
+
+impl Ord for Pass {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.0.wrapping_sub(other.0) as isize).cmp(&0)
+    }
+}
+
+impl PartialOrd for Pass {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+pub struct StrideScheduler {
+    /// ready tasks by pass, the smallest on top
+    ready: BinaryHeap<Reverse<(Pass, usize)>>,
+    /// pass and priority of each task, `None` before it is first added
+    tasks: Vec<Option<(Pass, usize)>>,
+    /// pass of the task picked last, new tasks start from it so that they
+    /// stay within `BIG_STRIDE / 2` of the others
+    current: Pass,
+}
+
+impl StrideScheduler {
+    pub fn new() -> Self {
+        Self {
+            ready: BinaryHeap::new(),
+            tasks: Vec::new(),
+            current: Pass::default(),
+        }
+    }
+}
+
+impl Scheduler for StrideScheduler {
+    fn name(&self) -> &'static str {
+        "stride"
+    }
+
+    fn add(&mut self, id: usize, priority: usize) {
+        if id >= self.tasks.len() {
+            self.tasks.resize(id + 1, None);
+        }
+        let pass = self.tasks[id].map_or(self.current, |(pass, _)| pass);
+        self.tasks[id] = Some((pass, priority));
+        self.ready.push(Reverse((pass, id)));
+    }
+
+    fn remove(&mut self, id: usize) {
+        if let Some(task) = self.tasks.get_mut(id) {
+            *task = None;
+        }
+        let ready = core::mem::take(&mut self.ready).into_vec();
+        self.ready = ready.into_iter().filter(|Reverse((_, ready))| *ready != id).collect();
+    }
+
+    fn pick_next(&mut self) -> Option<usize> {
+        let Reverse((pass, id)) = self.ready.pop()?;
+        self.current = pass;
+        if let Some((pass, priority)) = self.tasks[id].as_mut() {
+            pass.step(*priority);
+        }
+        Some(id)
+    }
+
+    fn on_tick(&mut self, _id: usize) {}
+
+    fn on_yield(&mut self, _id: usize) {}
+}
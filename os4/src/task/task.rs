@@ -1,13 +1,20 @@
 //! Types related to task management
 
 use super::TaskContext;
-use crate::mm::{MapPermission, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
-use crate::config::{kernel_stack_position, TRAP_CONTEXT, MAX_SYSCALL_NUM};
+use super::pid::{pid_alloc, KernelStack, PidHandle};
+use crate::mm::{ElfError, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::config::{TRAP_CONTEXT, MAX_SYSCALL_NUM, DEFAULT_PRIORITY};
 use crate::trap::{trap_handler, TrapContext};
+use crate::fs::{File, Stdin, Stdout};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 
 
 /// task control block structure
 pub struct TaskControlBlock {
+    pub pid: PidHandle,
+    pub kernel_stack: KernelStack,
     pub task_status: TaskStatus,
     pub task_cx: TaskContext,
     pub memory_set: MemorySet,
@@ -16,34 +23,42 @@ pub struct TaskControlBlock {
 
     pub task_start_time: usize,
     pub task_syscall_times: [u32; MAX_SYSCALL_NUM],
+    pub fd_table: Vec<Option<Arc<dyn File>>>,
+    /// at least 2, the share of the CPU is proportional to it
+    pub priority: usize,
+    /// pid of the parent, `None` for the tasks started at boot and orphans
+    pub parent: Option<usize>,
+    /// pids of the children, running or waiting to be reaped
+    pub children: Vec<usize>,
+    pub exit_code: i32,
 }
 
-#[derive(Copy, Clone, PartialEq)]
-/// task status: UnInit, Ready, Running, Exited
+#[derive(Copy, Clone, PartialEq, Eq)]
+/// task status: Ready, Running, Exited, Blocked, numbered as in the user
+/// library where 0 is UnInit
 pub enum TaskStatus {
-    UnInit,
-    Ready,
+    Ready = 1,
     Running,
+    /// a zombie until the parent reaps it with waitpid
     Exited,
+    /// waiting for a wakeup, off the scheduler
+    Blocked,
 }
 
 impl TaskControlBlock {
-    pub fn new(elf_data: &[u8], app_id: usize) -> Self {
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+    pub fn new(elf_data: &[u8]) -> Result<Self, ElfError> {
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data)?;
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
         let task_status = TaskStatus::Ready;
-        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(app_id);
-        KERNEL_SPACE
-            .exclusive_access()
-            .insert_frame_area(
-                kernel_stack_bottom.into(),
-                kernel_stack_top.into(),
-                MapPermission::R | MapPermission::W,
-            );
+        let pid = pid_alloc();
+        let kernel_stack = KernelStack::new(&pid)?;
+        let kernel_stack_top = kernel_stack.get_top();
         let task_control_block = Self {
+            pid,
+            kernel_stack,
             task_status,
             task_cx: TaskContext::goto_trap_return(kernel_stack_top),
             memory_set,
@@ -52,6 +67,18 @@ impl TaskControlBlock {
 
             task_start_time: 0,
             task_syscall_times: [0;MAX_SYSCALL_NUM],
+            fd_table: vec![
+                // 0 -> stdin
+                Some(Arc::new(Stdin)),
+                // 1 -> stdout
+                Some(Arc::new(Stdout)),
+                // 2 -> stderr
+                Some(Arc::new(Stdout)),
+            ],
+            priority: DEFAULT_PRIORITY,
+            parent: None,
+            children: Vec::new(),
+            exit_code: 0,
         };
 
 
@@ -63,16 +90,83 @@ impl TaskControlBlock {
             kernel_stack_top,
             trap_handler as usize,
         );
-        task_control_block
+        Ok(task_control_block)
+    }
+
+    /// A copy of this task with a new pid, which returns 0 from the fork
+    /// syscall. `ElfError::OutOfMemory` if frames ran out.
+    pub fn fork(&mut self) -> Result<Self, ElfError> {
+        let memory_set =
+            MemorySet::from_existed_user(&mut self.memory_set).ok_or(ElfError::OutOfMemory)?;
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn();
+        let pid = pid_alloc();
+        let kernel_stack = KernelStack::new(&pid)?;
+        let kernel_stack_top = kernel_stack.get_top();
+        let task_control_block = Self {
+            parent: Some(self.pid.0),
+            pid,
+            kernel_stack,
+            task_status: TaskStatus::Ready,
+            task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+            memory_set,
+            trap_cx_ppn,
+            base_size: self.base_size,
+
+            task_start_time: 0,
+            task_syscall_times: [0;MAX_SYSCALL_NUM],
+            fd_table: self.fd_table.clone(),
+            priority: self.priority,
+            children: Vec::new(),
+            exit_code: 0,
+        };
+        let trap_cx = task_control_block.get_trap_cx();
+        trap_cx.kernel_sp = kernel_stack_top;
+        trap_cx.x[10] = 0;
+        Ok(task_control_block)
+    }
+
+    /// Replace the address space with the app in `elf_data`, keeping the
+    /// pid and the open files.
+    pub fn exec(&mut self, elf_data: &[u8]) -> Result<(), ElfError> {
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data)?;
+        // files mapped shared get what was written to them
+        self.memory_set.sync_files();
+        self.memory_set = memory_set;
+        self.trap_cx_ppn = self
+            .memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn();
+        self.base_size = user_sp;
+        *self.get_trap_cx() = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            self.kernel_stack.get_top(),
+            trap_handler as usize,
+        );
+        Ok(())
     }
 
     pub fn get_trap_cx(&self) -> &'static mut TrapContext {
         self.trap_cx_ppn.get_mut()
     }
 
-    pub fn get_user_token(&self) -> usize {
+    pub fn get_user_token(&mut self) -> usize {
         self.memory_set.token()
     }
 
+    pub fn alloc_fd(&mut self) -> usize {
+        if let Some(fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
+            fd
+        } else {
+            self.fd_table.push(None);
+            self.fd_table.len() - 1
+        }
+    }
+
     
-}
\ No newline at end of file
+}
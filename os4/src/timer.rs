@@ -1,4 +1,4 @@
-use crate::config::CLOCK_FREQ;
+use crate::fdt::clock_freq;
 use crate::sbi::set_timer;
 use riscv::register::time;
 
@@ -10,9 +10,9 @@ pub fn get_time() -> usize {
 }
 
 pub fn get_time_us() -> usize {
-    time::read() / (CLOCK_FREQ / MICRO_PER_SEC)
+    time::read() / (clock_freq() / MICRO_PER_SEC)
 }
 
 pub fn set_next_trigger() {
-    set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
+    set_timer(get_time() + clock_freq() / TICKS_PER_SEC);
 }
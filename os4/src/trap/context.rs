@@ -1,7 +1,6 @@
 //! Implementation of [`TrapContext`]
 
 use riscv::register::sstatus::{self, Sstatus, SPP};
-use super::trap_return;
 
 
 #[repr(C)]
@@ -2,7 +2,8 @@ mod context;
 
 pub use context::TrapContext;
 use crate::syscall::syscall;
-use crate::task::{exit_current_and_run_next, suspend_current_and_run_next, current_user_token, current_trap_cx, increase_task_syscall_times};
+use crate::task::{exit_current_and_run_next, preempt_current_and_run_next, current_user_token, current_trap_cx, increase_task_syscall_times, handle_page_fault, dump_current_memory_set};
+use crate::mm::{AccessType, VirtAddr};
 use crate::timer::set_next_trigger;
 use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
 use core::arch::asm;
@@ -70,34 +71,55 @@ pub fn trap_return() -> ! {
             options(noreturn)
         );
     }
-    panic!("Unreachable in back_to_user!");
 }
 
 
 #[no_mangle]
 pub fn trap_handler() -> !{
     set_kernel_trap_entry();
-    let cx = current_trap_cx();
+    let mut cx = current_trap_cx();
     let scause = scause::read(); // get trap cause
     let stval = stval::read(); // get extra value
     match scause.cause() {
         Trap::Exception(Exception::UserEnvCall) => {
             increase_task_syscall_times(cx.x[17]);
             cx.sepc += 4;
-            cx.x[10] = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]) as usize;
+            let result = syscall(
+                cx.x[17],
+                [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
+            );
+            // exec moves the trap context
+            cx = current_trap_cx();
+            cx.x[10] = result as usize;
+        }
+        Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::InstructionPageFault) => {
+            let access = match scause.cause() {
+                Trap::Exception(Exception::StorePageFault) => AccessType::Write,
+                Trap::Exception(Exception::LoadPageFault) => AccessType::Read,
+                _ => AccessType::Execute,
+            };
+            // a kernel half address would alias a user page once truncated
+            if !VirtAddr::is_user_range(stval, 1) || !handle_page_fault(stval.into(), access) {
+                error!("[kernel] PageFault in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.", stval, cx.sepc);
+                dump_current_memory_set();
+                exit_current_and_run_next(-2);
+            }
         }
-        Trap::Exception(Exception::StoreFault) | Trap::Exception(Exception::StorePageFault) | Trap::Exception(Exception::LoadPageFault) 
-        => {
-            error!("[kernel] PageFault in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.", stval, cx.sepc);
-            exit_current_and_run_next();
+        Trap::Exception(Exception::StoreFault)
+        | Trap::Exception(Exception::LoadFault)
+        | Trap::Exception(Exception::InstructionFault) => {
+            error!("[kernel] AccessFault in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.", stval, cx.sepc);
+            exit_current_and_run_next(-2);
         }
         Trap::Exception(Exception::IllegalInstruction) => {
             error!("[kernel] IllegalInstruction in application, core dumped.");
-            exit_current_and_run_next();
+            exit_current_and_run_next(-3);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             set_next_trigger();
-            suspend_current_and_run_next();
+            preempt_current_and_run_next();
         }
         _ => {
             panic!(
@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exec, exit, fork, getpid, wait, waitpid};
+
+/*
+理想结果：输出 Test fork exec OK!
+*/
+
+const CHILDREN: i32 = 5;
+
+#[no_mangle]
+fn main() -> i32 {
+    let ppid = getpid();
+    assert!(ppid >= 0);
+    // 子进程有自己的 pid，父进程拿到它的退出码
+    let pid = fork();
+    if pid == 0 {
+        assert_ne!(ppid, getpid());
+        exit(7);
+    }
+    assert!(pid > 0 && pid != ppid);
+    let mut exit_code: i32 = 0;
+    assert_eq!(pid, waitpid(pid as usize, &mut exit_code));
+    assert_eq!(7, exit_code);
+    // 已回收的子进程不能再等
+    assert_eq!(-1, waitpid(pid as usize, &mut exit_code));
+    // 找不到的程序 exec 失败，进程继续运行
+    assert_eq!(-1, exec("ch4_no_such_app\0", &[]));
+    // exec 之后运行新程序，退出码来自新程序
+    let pid = fork();
+    if pid == 0 {
+        exec("ch2b_hello_world\0", &[]);
+        exit(-1);
+    }
+    assert_eq!(pid, waitpid(pid as usize, &mut exit_code));
+    assert_eq!(0, exit_code);
+    // 等待任意子进程，直到全部回收
+    for i in 0..CHILDREN {
+        if fork() == 0 {
+            exit(i);
+        }
+    }
+    let mut sum = 0;
+    for _ in 0..CHILDREN {
+        assert!(wait(&mut exit_code) > 0);
+        sum += exit_code;
+    }
+    assert_eq!((0..CHILDREN).sum::<i32>(), sum);
+    assert_eq!(-1, wait(&mut exit_code));
+    println!("Test fork exec OK!");
+    0
+}
@@ -15,7 +15,7 @@ fn main() -> i32 {
     let start: usize = 0x10000000;
     let len: usize = 4096;
     let prot: usize = 2;
-    assert_eq!(0, mmap(start, len, prot));
+    assert_eq!(start as isize, mmap(start, len, prot));
     let addr: *mut u8 = start as *mut u8;
     unsafe {
         // *addr = start as u8; // can't write, R == 0 && W == 1 is illegal in riscv
@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, mmap_flags, munmap, MmapFlags};
+
+/*
+理想结果：输出 Test mmap address OK!
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let len: usize = 4096;
+    let prot: usize = 3;
+    // 不给地址时由内核挑选，返回页对齐的地址
+    let picked = mmap_flags(0, len, prot, MmapFlags::empty());
+    assert!(picked > 0);
+    assert_eq!(0, picked as usize % 4096);
+    let addr = picked as usize as *mut u8;
+    unsafe {
+        *addr = 0x5a;
+        assert_eq!(*addr, 0x5a);
+    }
+    // 地址只是提示，已被占用时换一个
+    let start: usize = 0x10000000;
+    assert_eq!(start as isize, mmap(start, len, prot));
+    let other = mmap_flags(start, len, prot, MmapFlags::empty());
+    assert!(other > 0);
+    assert_ne!(start as isize, other);
+    // 同一地址不带 MAP_FIXED 不能再映射
+    assert_eq!(-1, mmap(start, len, prot));
+    // MAP_FIXED 替换原有映射，新页内容为 0
+    let addr = start as *mut u8;
+    unsafe {
+        *addr = 0x5a;
+    }
+    assert_eq!(start as isize, mmap_flags(start, len, prot, MmapFlags::FIXED));
+    unsafe {
+        assert_eq!(*addr, 0);
+    }
+    assert_eq!(0, munmap(start, len));
+    assert_eq!(0, munmap(other as usize, len));
+    assert_eq!(0, munmap(picked as usize, len));
+    println!("Test mmap address OK!");
+    0
+}
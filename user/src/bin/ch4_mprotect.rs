@@ -0,0 +1,81 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, mmap, mprotect, munmap, task_maps, waitpid};
+
+/*
+理想结果：输出 Test partial munmap and mprotect OK!
+*/
+
+const PAGE: usize = 4096;
+
+/// 在子进程中向 `addr` 写入，返回子进程的退出码
+fn write_in_child(addr: usize) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        unsafe {
+            *(addr as *mut u8) = 1;
+        }
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(pid, waitpid(pid as usize, &mut exit_code));
+    exit_code
+}
+
+fn areas() -> isize {
+    task_maps(&mut [])
+}
+
+#[no_mangle]
+fn main() -> i32 {
+    // 解除中间一页，两边的页保持不变
+    let start: usize = 0x10000000;
+    assert_eq!(start as isize, mmap(start, PAGE * 4, 3));
+    for i in 0..4 {
+        unsafe {
+            *((start + PAGE * i) as *mut u8) = i as u8 + 1;
+        }
+    }
+    assert_eq!(0, munmap(start + PAGE, PAGE));
+    for i in [0, 2, 3] {
+        unsafe {
+            assert_eq!(*((start + PAGE * i) as *const u8), i as u8 + 1);
+        }
+    }
+    assert_eq!(-2, write_in_child(start + PAGE));
+    // 范围中有未映射的页时失败
+    assert_eq!(-1, munmap(start, PAGE * 4));
+    // 跨越两个相邻映射解除
+    let start: usize = 0x10100000;
+    assert_eq!(start as isize, mmap(start, PAGE * 2, 3));
+    assert_eq!((start + PAGE * 2) as isize, mmap(start + PAGE * 2, PAGE * 2, 3));
+    assert_eq!(0, munmap(start + PAGE, PAGE * 2));
+    assert_eq!(0, write_in_child(start));
+    assert_eq!(0, write_in_child(start + PAGE * 3));
+    assert_eq!(-2, write_in_child(start + PAGE * 2));
+    // 把中间一页改成只读
+    let start: usize = 0x10200000;
+    assert_eq!(start as isize, mmap(start, PAGE * 3, 3));
+    unsafe {
+        *((start + PAGE) as *mut u8) = 7;
+    }
+    assert_eq!(0, mprotect(start + PAGE, PAGE, 1));
+    let split = areas();
+    unsafe {
+        assert_eq!(*((start + PAGE) as *const u8), 7);
+    }
+    assert_eq!(-2, write_in_child(start + PAGE));
+    assert_eq!(0, write_in_child(start));
+    assert_eq!(0, write_in_child(start + PAGE * 2));
+    // 恢复后相邻区域重新合并
+    assert_eq!(0, mprotect(start + PAGE, PAGE, 3));
+    assert_eq!(split - 2, areas());
+    assert_eq!(0, write_in_child(start + PAGE));
+    assert_eq!(-1, mprotect(start + PAGE * 3, PAGE, 3));
+    println!("Test partial munmap and mprotect OK!");
+    0
+}
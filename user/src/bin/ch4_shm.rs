@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, shmat, shmdt, shmget, waitpid};
+
+/*
+理想结果：输出 Test shared memory OK!
+*/
+
+const PAGE: usize = 4096;
+const KEY: usize = 42;
+
+fn wait_child(pid: isize) -> i32 {
+    let mut exit_code: i32 = 0;
+    assert_eq!(pid, waitpid(pid as usize, &mut exit_code));
+    exit_code
+}
+
+#[no_mangle]
+fn main() -> i32 {
+    let a: usize = 0x10000000;
+    let b: usize = 0x10100000;
+    let c: usize = 0x10200000;
+    assert_eq!(0, shmget(KEY, PAGE * 2));
+    // 已存在的段大小必须一致
+    assert_eq!(-1, shmget(KEY, PAGE * 3));
+    assert_eq!(0, shmget(KEY, PAGE * 2));
+    assert_eq!(-1, shmat(KEY + 1, a, 3));
+    // 同一个段映射两次，看到同样的内容
+    assert_eq!(0, shmat(KEY, a, 3));
+    assert_eq!(0, shmat(KEY, b, 1));
+    unsafe {
+        *(a as *mut u8) = 1;
+        assert_eq!(*(b as *const u8), 1);
+    }
+    // 只读的映射不能写
+    let pid = fork();
+    if pid == 0 {
+        unsafe {
+            *(b as *mut u8) = 2;
+        }
+        exit(0);
+    }
+    assert_eq!(-2, wait_child(pid));
+    // 子进程继承的映射和新映射都与父进程共享，而不是写时复制
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(0, shmat(KEY, c, 3));
+        unsafe {
+            *((a + PAGE) as *mut u8) = 3;
+            *(c as *mut u8) = 4;
+        }
+        exit(0);
+    }
+    assert_eq!(0, wait_child(pid));
+    unsafe {
+        assert_eq!(*((a + PAGE) as *const u8), 3);
+        assert_eq!(*(a as *const u8), 4);
+    }
+    assert_eq!(0, shmdt(a));
+    assert_eq!(-1, shmdt(a));
+    unsafe {
+        assert_eq!(*(b as *const u8), 4);
+    }
+    // 最后一个映射解除后段被释放，同一个 key 可以重新创建
+    assert_eq!(0, shmdt(b));
+    assert_eq!(0, shmget(KEY, PAGE * 3));
+    assert_eq!(0, shmat(KEY, a, 3));
+    unsafe {
+        assert_eq!(*(a as *const u8), 0);
+    }
+    assert_eq!(0, shmdt(a));
+    println!("Test shared memory OK!");
+    0
+}
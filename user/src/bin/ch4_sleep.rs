@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, get_time, sleep_blocking, task_info, wait, TaskInfo, SYSCALL_SLEEP, SYSCALL_YIELD,
+};
+
+/*
+理想结果：输出 Test sleep blocking OK!
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let t1 = get_time();
+    sleep_blocking(200);
+    let t2 = get_time();
+    assert!(t2 - t1 >= 200);
+    // 睡眠期间任务阻塞在内核中，而不是循环 yield
+    let info = TaskInfo::new();
+    assert_eq!(0, task_info(&info));
+    assert_eq!(1, info.syscall_times[SYSCALL_SLEEP]);
+    assert_eq!(0, info.syscall_times[SYSCALL_YIELD]);
+    // 睡得短的子进程先醒来、先退出
+    let long = fork();
+    if long == 0 {
+        sleep_blocking(300);
+        exit(0);
+    }
+    let short = fork();
+    if short == 0 {
+        sleep_blocking(100);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(short, wait(&mut exit_code));
+    assert_eq!(long, wait(&mut exit_code));
+    println!("Test sleep blocking OK!");
+    0
+}
@@ -15,15 +15,44 @@ extern crate bitflags;
 
 use alloc::vec::Vec;
 use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
 pub use console::{flush, STDIN, STDOUT};
 pub use syscall::*;
 
 const USER_HEAP_SIZE: usize = 16384;
+/// the heap grows through brk by at least this many bytes at a time
+const HEAP_GROW_SIZE: usize = 0x4000;
 
 static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
 
+/// A buddy heap starting in `HEAP_SPACE` and growing with `sbrk` once full
+struct GrowableHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        if let Ok(ptr) = heap.alloc(layout) {
+            return ptr.as_ptr();
+        }
+        // buddy blocks are aligned to their size, so twice the block size
+        // always holds one
+        let size = (layout.size().max(layout.align()).next_power_of_two() * 2).max(HEAP_GROW_SIZE);
+        let start = sbrk(size as isize);
+        if start < 0 {
+            return null_mut();
+        }
+        heap.add_to_heap(start as usize, start as usize + size);
+        heap.alloc(layout).map_or(null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+}
+
 #[global_allocator]
-static HEAP: LockedHeap = LockedHeap::empty();
+static HEAP: GrowableHeap = GrowableHeap(LockedHeap::empty());
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -49,7 +78,7 @@ fn clear_bss() {
 pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
     clear_bss();
     unsafe {
-        HEAP.lock()
+        HEAP.0.lock()
             .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
     }
     let mut v: Vec<&'static str> = Vec::new();
@@ -85,6 +114,16 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct MmapFlags: usize {
+        const SHARED = 0x01;
+        const PRIVATE = 0x02;
+        const FIXED = 0x10;
+        const ANONYMOUS = 0x20;
+        const FIXED_NOREPLACE = 0x10_0000;
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Default)]
 pub struct TimeVal {
@@ -104,6 +143,7 @@ pub enum TaskStatus {
     Ready,
     Running,
     Exited,
+    Blocked,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -131,6 +171,34 @@ impl TaskInfo {
     }
 }
 
+/// Resident pages of a task by kind of area, and how many pages it may map
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct MemoryUsage {
+    pub code: usize,
+    pub data: usize,
+    /// the user stack and the trap context
+    pub stack: usize,
+    /// mmap and shared memory
+    pub mmap: usize,
+    pub page_table: usize,
+    pub limit: usize,
+}
+
+/// An area of the address space, as listed by `task_maps`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct VmaInfo {
+    pub start_vpn: usize,
+    pub end_vpn: usize,
+    /// 0 identical, 1 framed, 2 identical with huge pages, 3 shared
+    pub map_type: usize,
+    /// R = 1 << 1, W = 1 << 2, X = 1 << 3, U = 1 << 4
+    pub perm: usize,
+    /// pages backed by a frame
+    pub resident: usize,
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct Stat {
@@ -284,14 +352,67 @@ pub fn sleep(period_ms: usize) {
         sys_yield();
     }
 }
+/// Map anonymous memory at exactly `start`, failing if the range is taken.
+/// Returns the address.
 pub fn mmap(start: usize, len: usize, prot: usize) -> isize {
-    sys_mmap(start, len, prot)
+    mmap_flags(start, len, prot, MmapFlags::FIXED_NOREPLACE)
+}
+
+/// Map private anonymous memory with extra `flags`, `start` is only a hint
+/// without `MmapFlags::FIXED`. Returns the address.
+pub fn mmap_flags(start: usize, len: usize, prot: usize, flags: MmapFlags) -> isize {
+    let flags = flags | MmapFlags::PRIVATE | MmapFlags::ANONYMOUS;
+    sys_mmap(start, len, prot, flags.bits, usize::MAX, 0)
+}
+
+/// Map `len` bytes of file `fd` from `offset`, with `MmapFlags::SHARED`
+/// or `MmapFlags::PRIVATE` in `flags`. Returns the address.
+pub fn mmap_file(start: usize, len: usize, prot: usize, flags: MmapFlags, fd: usize, offset: usize) -> isize {
+    sys_mmap(start, len, prot, flags.bits, fd, offset)
+}
+
+/// Write what was stored in shared file mappings back to the files.
+pub fn msync(start: usize, len: usize) -> isize {
+    sys_msync(start, len, 0)
+}
+
+/// Set the program break, returning the new break or the old one on failure.
+pub fn brk(addr: usize) -> isize {
+    sys_brk(addr)
+}
+
+/// Move the program break by `increment` bytes, returning the old break or
+/// -1 if it could not move.
+pub fn sbrk(increment: isize) -> isize {
+    let old = sys_brk(0);
+    let new = old + increment;
+    if sys_brk(new as usize) == new {
+        old
+    } else {
+        -1
+    }
 }
 
 pub fn munmap(start: usize, len: usize) -> isize {
     sys_munmap(start, len)
 }
 
+pub fn mprotect(start: usize, len: usize, prot: usize) -> isize {
+    sys_mprotect(start, len, prot)
+}
+
+pub fn shmget(key: usize, len: usize) -> isize {
+    sys_shmget(key, len)
+}
+
+pub fn shmat(key: usize, start: usize, prot: usize) -> isize {
+    sys_shmat(key, start, prot)
+}
+
+pub fn shmdt(start: usize) -> isize {
+    sys_shmdt(start)
+}
+
 pub fn spawn(path: &str) -> isize {
     sys_spawn(path)
 }
@@ -307,6 +428,16 @@ pub fn task_info(info: &TaskInfo) -> isize {
     sys_task_info(info)
 }
 
+pub fn task_memory(usage: &mut MemoryUsage) -> isize {
+    sys_task_memory(usage)
+}
+
+/// Fill `buf` with the areas of the address space in address order and
+/// return how many there are, which may be more than `buf` holds.
+pub fn task_maps(buf: &mut [VmaInfo]) -> isize {
+    sys_task_maps(buf)
+}
+
 pub fn thread_create(entry: usize, arg: usize) -> isize {
     sys_thread_create(entry, arg)
 }
@@ -1,6 +1,6 @@
 use crate::TaskInfo;
 
-use super::{Stat, TimeVal};
+use super::{MemoryUsage, Stat, TimeVal, VmaInfo};
 
 pub const SYSCALL_OPENAT: usize = 56;
 pub const SYSCALL_CLOSE: usize = 57;
@@ -21,12 +21,20 @@ pub const SYSCALL_WAITPID: usize = 260;
 pub const SYSCALL_SET_PRIORITY: usize = 140;
 pub const SYSCALL_MUNMAP: usize = 215;
 pub const SYSCALL_MMAP: usize = 222;
+pub const SYSCALL_BRK: usize = 214;
+pub const SYSCALL_MPROTECT: usize = 226;
+pub const SYSCALL_MSYNC: usize = 227;
+pub const SYSCALL_SHMGET: usize = 194;
+pub const SYSCALL_SHMAT: usize = 196;
+pub const SYSCALL_SHMDT: usize = 197;
 pub const SYSCALL_SPAWN: usize = 400;
 pub const SYSCALL_MAIL_READ: usize = 401;
 pub const SYSCALL_MAIL_WRITE: usize = 402;
 pub const SYSCALL_DUP: usize = 24;
 pub const SYSCALL_PIPE: usize = 59;
 pub const SYSCALL_TASK_INFO: usize = 410;
+pub const SYSCALL_TASK_MEMORY: usize = 411;
+pub const SYSCALL_TASK_MAPS: usize = 412;
 pub const SYSCALL_THREAD_CREATE: usize = 460;
 pub const SYSCALL_WAITTID: usize = 462;
 pub const SYSCALL_MUTEX_CREATE: usize = 463;
@@ -181,14 +189,38 @@ pub fn sys_set_priority(prio: isize) -> isize {
     syscall(SYSCALL_SET_PRIORITY, [prio as usize, 0, 0])
 }
 
-pub fn sys_mmap(start: usize, len: usize, prot: usize) -> isize {
-    syscall(SYSCALL_MMAP, [start, len, prot])
+pub fn sys_mmap(start: usize, len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> isize {
+    syscall6(SYSCALL_MMAP, [start, len, prot, flags, fd, offset])
 }
 
 pub fn sys_munmap(start: usize, len: usize) -> isize {
     syscall(SYSCALL_MUNMAP, [start, len, 0])
 }
 
+pub fn sys_brk(addr: usize) -> isize {
+    syscall(SYSCALL_BRK, [addr, 0, 0])
+}
+
+pub fn sys_msync(start: usize, len: usize, flags: usize) -> isize {
+    syscall(SYSCALL_MSYNC, [start, len, flags])
+}
+
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> isize {
+    syscall(SYSCALL_MPROTECT, [start, len, prot])
+}
+
+pub fn sys_shmget(key: usize, len: usize) -> isize {
+    syscall(SYSCALL_SHMGET, [key, len, 0])
+}
+
+pub fn sys_shmat(key: usize, start: usize, prot: usize) -> isize {
+    syscall(SYSCALL_SHMAT, [key, start, prot])
+}
+
+pub fn sys_shmdt(start: usize) -> isize {
+    syscall(SYSCALL_SHMDT, [start, 0, 0])
+}
+
 pub fn sys_spawn(path: &str) -> isize {
     syscall(SYSCALL_SPAWN, [path.as_ptr() as usize, 0, 0])
 }
@@ -205,6 +237,14 @@ pub fn sys_task_info(info: &TaskInfo) -> isize {
     syscall(SYSCALL_TASK_INFO, [info as *const _ as usize, 0, 0])
 }
 
+pub fn sys_task_memory(usage: &mut MemoryUsage) -> isize {
+    syscall(SYSCALL_TASK_MEMORY, [usage as *mut _ as usize, 0, 0])
+}
+
+pub fn sys_task_maps(buf: &mut [VmaInfo]) -> isize {
+    syscall(SYSCALL_TASK_MAPS, [buf.as_mut_ptr() as usize, buf.len(), 0])
+}
+
 pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
     syscall(SYSCALL_THREAD_CREATE, [entry, arg, 0])
 }